@@ -0,0 +1,253 @@
+use crate::console;
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::modules::{Files, Format, Location};
+use crate::pack::{self, ARCHIVE_EXTENSION, CHECKSUM_FILE};
+use crate::runtime::Script;
+use crate::typescript;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Largest entry an archive may hold once uncompressed. Modules are scripts
+/// and an icon, so anything bigger is a broken or malicious archive.
+const MAX_ENTRY_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Largest total an archive may hold once uncompressed.
+const MAX_ARCHIVE_BYTES: u64 = 128 * 1024 * 1024;
+
+/// A packed module, read fully into memory.
+pub struct Archive {
+    pub entries: BTreeMap<String, Vec<u8>>,
+    pub comment: String,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Archive, String> {
+        let error =
+            |err: zip::result::ZipError| format!("Could not read {}: {}", path.display(), err);
+
+        let file = File::open(path)
+            .map_err(|err| format!("Could not open {}: {}", path.display(), err))?;
+        let mut zip = zip::ZipArchive::new(file).map_err(error)?;
+        let comment = String::from_utf8_lossy(zip.comment()).to_string();
+
+        let mut entries = BTreeMap::new();
+        let mut total = 0;
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index).map_err(error)?;
+            if file.is_dir() {
+                continue;
+            }
+            let name = file
+                .enclosed_name()
+                .and_then(|name| pack::normalise(&name))
+                .ok_or_else(|| {
+                    format!(
+                        "{} contains an unsafe path: {}",
+                        path.display(),
+                        file.name()
+                    )
+                })?;
+
+            // The sizes in the archive are not trusted: reading stops one
+            // byte past the limit, which is enough to tell it was exceeded.
+            let mut bytes = Vec::new();
+            (&mut file)
+                .take(MAX_ENTRY_BYTES + 1)
+                .read_to_end(&mut bytes)
+                .map_err(|err| {
+                    format!("Could not read {} from {}: {}", name, path.display(), err)
+                })?;
+            if bytes.len() as u64 > MAX_ENTRY_BYTES {
+                return Err(format!(
+                    "{} in {} is larger than {} MiB.",
+                    name,
+                    path.display(),
+                    MAX_ENTRY_BYTES / 1024 / 1024
+                ));
+            }
+            total += bytes.len() as u64;
+            if total > MAX_ARCHIVE_BYTES {
+                return Err(format!(
+                    "{} is larger than {} MiB once extracted.",
+                    path.display(),
+                    MAX_ARCHIVE_BYTES / 1024 / 1024
+                ));
+            }
+            entries.insert(name, bytes);
+        }
+
+        Ok(Archive { entries, comment })
+    }
+
+    pub fn manifest(&self) -> Result<Manifest, String> {
+        let bytes = self
+            .entries
+            .get(MANIFEST_FILE)
+            .ok_or_else(|| format!("Archive does not contain {}.", MANIFEST_FILE))?;
+        Manifest::parse(&String::from_utf8_lossy(bytes))
+            .map_err(|err| format!("{}: {}", MANIFEST_FILE, err))
+    }
+
+    /// Returns the name and source of the script the manifest points to.
+    pub fn script(&self) -> Result<(String, String), String> {
+        let manifest = self.manifest()?;
+        let name = pack::normalise(Path::new(&manifest.script))
+            .ok_or_else(|| format!("{} does not declare a valid script.", MANIFEST_FILE))?;
+        let bytes = self
+            .entries
+            .get(&name)
+            .ok_or_else(|| format!("Archive does not contain the script {}.", name))?;
+        let source = String::from_utf8(bytes.clone())
+            .map_err(|_| format!("The script {} is not valid UTF-8.", name))?;
+        Ok((name, source))
+    }
+
+    /// Recomputes the checksums and lists every entry that does not match
+    /// what was recorded when the archive was packed.
+    pub fn verify(&self) -> Vec<String> {
+        let recorded: serde_json::Value = match self.entries.get(CHECKSUM_FILE) {
+            Some(bytes) => match serde_json::from_slice(bytes) {
+                Ok(recorded) => recorded,
+                Err(err) => return vec![format!("{} is not valid JSON: {}", CHECKSUM_FILE, err)],
+            },
+            None => return vec![format!("archive does not contain {}", CHECKSUM_FILE)],
+        };
+
+        let (actual, digest) = pack::checksum(&self.entries);
+        let actual: serde_json::Value = serde_json::from_str(&actual).unwrap();
+
+        let mut problems = Vec::new();
+        let empty = serde_json::Map::new();
+        let recorded_files = recorded["files"].as_object().unwrap_or(&empty);
+        let actual_files = actual["files"].as_object().unwrap();
+
+        for (name, hash) in actual_files {
+            match recorded_files.get(name) {
+                Some(recorded) if recorded == hash => {}
+                Some(_) => problems.push(format!("{} was modified after packing", name)),
+                None => problems.push(format!("{} was added after packing", name)),
+            }
+        }
+        for name in recorded_files.keys() {
+            if !actual_files.contains_key(name) {
+                problems.push(format!("{} was removed after packing", name));
+            }
+        }
+
+        if recorded["digest"].as_str() != Some(digest.as_str()) && problems.is_empty() {
+            problems.push(format!("digest in {} does not match", CHECKSUM_FILE));
+        }
+        if self.comment != format!("sha256:{}", recorded["digest"].as_str().unwrap_or("")) {
+            problems.push("archive comment does not match the recorded digest".to_string());
+        }
+
+        problems
+    }
+
+    /// Writes every entry below `dir` and returns the paths it created.
+    ///
+    /// Refuses to touch a directory that already has files in it unless
+    /// `force` is set.
+    pub fn extract(&self, dir: &Path, force: bool) -> Result<Vec<PathBuf>, String> {
+        let occupied = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+        if occupied && !force {
+            return Err(format!(
+                "{} already exists and is not empty, pass --force to overwrite it.",
+                dir.display()
+            ));
+        }
+
+        let mut created = Vec::with_capacity(self.entries.len());
+        for (name, bytes) in &self.entries {
+            let path = dir.join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|err| format!("Could not create {}: {}", parent.display(), err))?;
+            }
+            fs::write(&path, bytes)
+                .map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+            created.push(path);
+        }
+        Ok(created)
+    }
+}
+
+/// Whether `path` looks like a packed module rather than a script.
+pub fn is_archive(path: &Path) -> bool {
+    if path.extension().and_then(|ext| ext.to_str()) == Some(ARCHIVE_EXTENSION) {
+        return true;
+    }
+    let mut magic = [0; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == b"PK\x03\x04")
+}
+
+/// Reads the script to run from a script file, a module directory or a
+/// packed module, along with its source map if it has one.
+pub fn load_script(path: &Path) -> Result<Script, String> {
+    if path.is_dir() {
+        let manifest = Manifest::load(path)?;
+        let entry = pack::normalise(Path::new(&manifest.script))
+            .ok_or_else(|| format!("{} does not declare a valid script.", MANIFEST_FILE))?;
+        let mut script = read_script(path, &entry)?;
+        script.module_type = manifest.module_type;
+        return Ok(script);
+    }
+
+    if is_archive(path) {
+        let archive = Archive::open(path)?;
+        for problem in archive.verify() {
+            let message = format!("warning: {}: {}", path.display(), problem);
+            console::log(console::Level::Warn, &message);
+        }
+        let (entry, source) = archive.script()?;
+        let module_type = archive.manifest()?.module_type;
+        let files = Files {
+            entry,
+            location: Location::Archive(path.to_path_buf(), Arc::new(archive.entries)),
+        };
+        let mut script = script(files, source)?;
+        script.module_type = module_type;
+        return Ok(script);
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let entry = path.file_name().unwrap_or_default().to_string_lossy();
+    read_script(dir, &entry)
+}
+
+/// Reads the script at `entry` in the module directory `dir`.
+pub fn read_script(dir: &Path, entry: &str) -> Result<Script, String> {
+    let path = dir.join(entry);
+    let source = fs::read_to_string(&path)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    let files = Files {
+        entry: entry.to_string(),
+        location: Location::Dir(dir.to_path_buf()),
+    };
+    script(files, source)
+}
+
+/// Builds the script at `files.entry`, transpiling TypeScript and picking
+/// up its source map.
+fn script(files: Files, source: String) -> Result<Script, String> {
+    let name = files.name(&files.entry);
+    let source = if files.is_typescript(&files.entry) {
+        typescript::transpile(&name, &source)?
+    } else {
+        source
+    };
+    let source_map = files.source_map(&files.entry, &source);
+    Ok(Script {
+        name,
+        source,
+        source_map: source_map.map(Arc::new),
+        format: Format::Auto,
+        module_type: None,
+        files: Arc::new(files),
+    })
+}
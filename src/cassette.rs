@@ -0,0 +1,466 @@
+use crate::http::{Request, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What a cassette does with the requests a module makes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Requests go to the network and every exchange is saved.
+    Record,
+    /// Requests are answered from the saved exchanges only.
+    Replay,
+}
+
+/// A directory of recorded HTTP exchanges, one JSON file per request.
+/// Recording starts from an empty cassette, see `Cassette::clear`.
+///
+/// Requests are matched on their method, url and body. Headers are saved
+/// for reference but ignored when matching, since they often carry cookies
+/// or timestamps. A request that is made several times is saved once per
+/// occurrence and replayed in the same order; once those run out, the last
+/// recorded response is served again. Occurrences are counted when requests
+/// are sent, see `Cassette::start`, so requests in flight together keep the
+/// order the script made them in.
+///
+/// Bodies are saved as text when they are UTF-8 and as base64 otherwise.
+pub struct Cassette {
+    dir: PathBuf,
+    mode: Mode,
+    occurrences: Mutex<HashMap<String, usize>>,
+    misses: Mutex<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_encoding: Option<Encoding>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordedResponse {
+    status: i32,
+    status_text: String,
+    headers: BTreeMap<String, String>,
+    body: String,
+    /// How `body` encodes the bytes, left out when it is the text itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_encoding: Option<Encoding>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    Base64,
+}
+
+/// Saves `bytes` as text if they are UTF-8 and as base64 otherwise.
+fn encode_body(bytes: &[u8]) -> (String, Option<Encoding>) {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (STANDARD.encode(bytes), Some(Encoding::Base64)),
+    }
+}
+
+fn decode_body(body: String, encoding: Option<Encoding>) -> Result<Vec<u8>, String> {
+    match encoding {
+        None => Ok(body.into_bytes()),
+        Some(Encoding::Base64) => STANDARD
+            .decode(body)
+            .map_err(|err| format!("the body is not valid base64: {}", err)),
+    }
+}
+
+/// One request made through a cassette, which knows the occurrence it was
+/// given when it was sent.
+pub struct Entry {
+    cassette: Arc<Cassette>,
+    key: String,
+    occurrence: usize,
+}
+
+impl Cassette {
+    pub fn new(dir: &Path, mode: Mode) -> Result<Cassette, String> {
+        match mode {
+            Mode::Record => fs::create_dir_all(dir)
+                .map_err(|err| format!("Could not create {}: {}", dir.display(), err))?,
+            Mode::Replay if !dir.is_dir() => {
+                return Err(format!("Cassette {} does not exist.", dir.display()))
+            }
+            Mode::Replay => {}
+        }
+
+        Ok(Cassette {
+            dir: dir.to_path_buf(),
+            mode,
+            occurrences: Mutex::new(HashMap::new()),
+            misses: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Deletes the recorded exchanges in `dir`, so recording again does not
+    /// leave entries behind for requests the module no longer makes.
+    pub fn clear(dir: &Path) -> Result<(), String> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        for entry in entries {
+            let path = entry
+                .map_err(|err| format!("Could not read {}: {}", dir.display(), err))?
+                .path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+                fs::remove_file(&path)
+                    .map_err(|err| format!("Could not delete {}: {}", path.display(), err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes every request sent from the isolate go through the cassette.
+    pub fn install(self: &Arc<Self>, isolate: &mut v8::Isolate) {
+        isolate.set_slot(self.clone());
+    }
+
+    /// Returns the cassette installed on the isolate behind `scope`, if any.
+    pub fn get(scope: &mut v8::HandleScope) -> Option<Arc<Cassette>> {
+        scope.get_slot::<Arc<Cassette>>().cloned()
+    }
+
+    /// Requests that were made during replay but are not in the cassette.
+    pub fn misses(&self) -> Vec<String> {
+        self.misses.lock().unwrap().clone()
+    }
+
+    /// Counts `request` as made now and returns the entry to replay or
+    /// record it with.
+    pub fn start(self: &Arc<Self>, request: &Request) -> Entry {
+        let key = Cassette::key(request);
+        let mut occurrences = self.occurrences.lock().unwrap();
+        let count = occurrences.entry(key.clone()).or_insert(0);
+        let occurrence = *count;
+        *count += 1;
+        Entry {
+            cassette: self.clone(),
+            key,
+            occurrence,
+        }
+    }
+
+    /// The file name of a request, without the occurrence.
+    fn key(request: &Request) -> String {
+        let mut hash = Sha256::new();
+        hash.update(request.method.as_str());
+        hash.update("\n");
+        hash.update(&request.url);
+        hash.update("\n");
+        if let Some(body) = &request.body {
+            hash.update(body);
+        }
+        format!(
+            "{}-{}",
+            request.method.as_str().to_ascii_lowercase(),
+            &format!("{:x}", hash.finalize())[..16]
+        )
+    }
+
+    fn path(&self, key: &str, occurrence: usize) -> PathBuf {
+        if occurrence == 0 {
+            self.dir.join(format!("{}.json", key))
+        } else {
+            self.dir.join(format!("{}-{}.json", key, occurrence + 1))
+        }
+    }
+}
+
+impl Entry {
+    pub fn mode(&self) -> Mode {
+        self.cassette.mode
+    }
+
+    /// Serves the recorded response for `request`.
+    pub fn replay(&self, request: &Request) -> Result<Response, String> {
+        let cassette = &self.cassette;
+        let path = (0..=self.occurrence)
+            .rev()
+            .map(|occurrence| cassette.path(&self.key, occurrence))
+            .find(|path| path.is_file());
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let description = format!("{} {}", request.method, request.url);
+                cassette.misses.lock().unwrap().push(description.clone());
+                return Err(format!(
+                    "{} is not in the cassette {}.",
+                    description,
+                    cassette.dir.display()
+                ));
+            }
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        let invalid = |err| format!("{} is not a valid cassette entry: {}", path.display(), err);
+        let exchange: Exchange =
+            serde_json::from_str(&content).map_err(|err| invalid(err.to_string()))?;
+
+        let response = exchange.response;
+        let content_type = response
+            .headers
+            .get("content-type")
+            .cloned()
+            .unwrap_or_default();
+        Ok(Response {
+            status_code: response.status,
+            status_text: response.status_text,
+            body: decode_body(response.body, response.body_encoding).map_err(invalid)?,
+            content_type,
+            headers: response.headers.into_iter().collect(),
+        })
+    }
+
+    /// Saves the exchange so it can be replayed later.
+    pub fn record(&self, request: &Request, response: &Response) -> Result<(), String> {
+        let path = self.cassette.path(&self.key, self.occurrence);
+
+        let (request_body, request_encoding) = match &request.body {
+            Some(body) => {
+                let (body, encoding) = encode_body(body);
+                (Some(body), encoding)
+            }
+            None => (None, None),
+        };
+        let (response_body, response_encoding) = encode_body(&response.body);
+        let exchange = Exchange {
+            request: RecordedRequest {
+                method: request.method.to_string(),
+                url: request.url.clone(),
+                headers: request
+                    .headers
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+                        (name.to_string(), value)
+                    })
+                    .collect(),
+                body: request_body,
+                body_encoding: request_encoding,
+            },
+            response: RecordedResponse {
+                status: response.status_code,
+                status_text: response.status_text.clone(),
+                headers: response.headers.clone().into_iter().collect(),
+                body: response_body,
+                body_encoding: response_encoding,
+            },
+        };
+
+        let json = serde_json::to_string_pretty(&exchange).unwrap() + "\n";
+        fs::write(&path, json).map_err(|err| format!("Could not write {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use reqwest::Method;
+
+    fn request(method: Method, url: &str, body: Option<&str>) -> Request {
+        Request {
+            url: url.to_string(),
+            method,
+            headers: HeaderMap::new(),
+            body: body.map(|body| body.as_bytes().to_vec()),
+        }
+    }
+
+    fn response(body: &[u8]) -> Response {
+        Response {
+            status_code: 200,
+            status_text: "OK".to_string(),
+            body: body.to_vec(),
+            content_type: "text/plain".to_string(),
+            headers: HashMap::from([("content-type".to_string(), "text/plain".to_string())]),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chouten-cassette-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open(dir: &Path, mode: Mode) -> Arc<Cassette> {
+        Arc::new(Cassette::new(dir, mode).unwrap())
+    }
+
+    #[test]
+    fn keys_on_method_url_and_body() {
+        let key = Cassette::key;
+        let get = key(&request(Method::GET, "https://example.com/a", None));
+
+        assert!(get.starts_with("get-"));
+        assert_eq!(get.len(), "get-".len() + 16);
+        assert_eq!(
+            key(&request(Method::GET, "https://example.com/a", None)),
+            get
+        );
+        assert_ne!(
+            key(&request(Method::GET, "https://example.com/b", None)),
+            get
+        );
+
+        let post = key(&request(Method::POST, "https://example.com/a", Some("q=1")));
+        assert!(post.starts_with("post-"));
+        assert_ne!(
+            key(&request(Method::POST, "https://example.com/a", Some("q=2"))),
+            post
+        );
+
+        // Headers are not part of the key.
+        let mut with_cookie = request(Method::GET, "https://example.com/a", None);
+        with_cookie
+            .headers
+            .insert("cookie", HeaderValue::from_static("session=1"));
+        assert_eq!(key(&with_cookie), get);
+    }
+
+    #[test]
+    fn counts_occurrences_per_key() {
+        let dir = temp_dir("occurrences");
+        let cassette = open(&dir, Mode::Record);
+        let a = request(Method::GET, "https://example.com/a", None);
+        let b = request(Method::GET, "https://example.com/b", None);
+
+        assert_eq!(cassette.start(&a).occurrence, 0);
+        assert_eq!(cassette.start(&b).occurrence, 0);
+        assert_eq!(cassette.start(&a).occurrence, 1);
+
+        let key = Cassette::key(&a);
+        assert_eq!(cassette.path(&key, 0), dir.join(format!("{}.json", key)));
+        assert_eq!(cassette.path(&key, 1), dir.join(format!("{}-2.json", key)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replays_what_was_recorded_in_order() {
+        let dir = temp_dir("replay");
+        let a = request(Method::GET, "https://example.com/a", None);
+
+        let recorder = open(&dir, Mode::Record);
+        recorder.start(&a).record(&a, &response(b"first")).unwrap();
+        recorder.start(&a).record(&a, &response(b"second")).unwrap();
+
+        let player = open(&dir, Mode::Replay);
+        let bodies: Vec<_> = (0..3)
+            .map(|_| player.start(&a).replay(&a).unwrap().body)
+            .collect();
+        // The last response is served again once the recorded ones run out.
+        assert_eq!(
+            bodies,
+            [b"first".to_vec(), b"second".to_vec(), b"second".to_vec()]
+        );
+        assert_eq!(
+            player.start(&a).replay(&a).unwrap().content_type,
+            "text/plain"
+        );
+        assert!(player.misses().is_empty());
+
+        let missing = request(Method::GET, "https://example.com/missing", None);
+        assert!(player.start(&missing).replay(&missing).is_err());
+        assert_eq!(player.misses(), ["GET https://example.com/missing"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbers_requests_in_the_order_they_start() {
+        let dir = temp_dir("order");
+        let a = request(Method::GET, "https://example.com/a", None);
+
+        // The second request finishes first, as happens with `Promise.all`.
+        let recorder = open(&dir, Mode::Record);
+        let first = recorder.start(&a);
+        let second = recorder.start(&a);
+        second.record(&a, &response(b"second")).unwrap();
+        first.record(&a, &response(b"first")).unwrap();
+
+        let player = open(&dir, Mode::Replay);
+        let first = player.start(&a);
+        let second = player.start(&a);
+        assert_eq!(second.replay(&a).unwrap().body, b"second");
+        assert_eq!(first.replay(&a).unwrap().body, b"first");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_binary_bodies_as_they_are() {
+        let dir = temp_dir("binary");
+        let binary = [0x00, 0xff, 0xfe, 0x80, 0xc3, 0x28, 0x7f];
+        let post = Request {
+            body: Some(binary.to_vec()),
+            ..request(Method::POST, "https://example.com/upload", None)
+        };
+        let get = request(Method::GET, "https://example.com/text", None);
+
+        let recorder = open(&dir, Mode::Record);
+        recorder
+            .start(&post)
+            .record(&post, &response(&binary))
+            .unwrap();
+        recorder
+            .start(&get)
+            .record(&get, &response("ünïcode".as_bytes()))
+            .unwrap();
+
+        // Text stays readable in the file, anything else is base64.
+        let read = |request: &Request| -> serde_json::Value {
+            let path = dir.join(format!("{}.json", Cassette::key(request)));
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let saved = read(&post);
+        assert_eq!(saved["request"]["body"], STANDARD.encode(binary));
+        assert_eq!(saved["request"]["bodyEncoding"], "base64");
+        assert_eq!(saved["response"]["body"], STANDARD.encode(binary));
+        assert_eq!(saved["response"]["bodyEncoding"], "base64");
+        let saved = read(&get);
+        assert_eq!(saved["response"]["body"], "ünïcode");
+        assert!(saved["response"].get("bodyEncoding").is_none());
+
+        let player = open(&dir, Mode::Replay);
+        assert_eq!(player.start(&post).replay(&post).unwrap().body, binary);
+        assert_eq!(
+            player.start(&get).replay(&get).unwrap().body,
+            "ünïcode".as_bytes()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_needs_an_existing_cassette() {
+        let dir = temp_dir("missing");
+        assert!(Cassette::new(&dir, Mode::Replay).is_err());
+    }
+}
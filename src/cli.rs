@@ -0,0 +1,376 @@
+use crate::cassette;
+use crate::console;
+use crate::modules::Format;
+use crate::scaffold::Template;
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How `chouten run` reports what went wrong, see `InvokeError::exit_code`.
+const RUN_EXIT_CODES: &str = "Exit codes:
+  1  the call failed otherwise, or its result is invalid
+  2  the command line is invalid
+  3  the script does not compile
+  4  the script threw an exception
+  5  the entry point's promise was rejected
+  6  the entry point's promise did not settle";
+
+#[derive(Parser)]
+#[command(
+    name = "chouten",
+    version,
+    about = "A cli for testing and creating chouten modules"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Hide console messages less severe than this
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value = "info",
+        env = "CHOUTEN_LOG_LEVEL"
+    )]
+    pub log_level: console::Level,
+
+    /// Write console messages and diagnostics to this file instead of stderr
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Drop console messages and diagnostics, leaving only results and errors
+    #[arg(long, global = true, conflicts_with = "log_file")]
+    pub quiet: bool,
+
+    /// Run timers on a virtual clock that skips ahead whenever the module is
+    /// only waiting for one, so sleeps and backoff return immediately
+    #[arg(long, global = true, env = "CHOUTEN_FAKE_TIME")]
+    pub fake_time: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Call one of the module's entry points and print the result
+    #[command(subcommand, after_help = RUN_EXIT_CODES)]
+    Run(RunCommand),
+    /// Create a new module from a template
+    New(NewArgs),
+    /// Check a module's manifest, icon and entry points
+    Validate {
+        /// Directory of the module
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Run the test cases of a module and report which ones fail
+    Test {
+        /// Directory of the module
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+        /// Test file to run [default: <dir>/tests/module.toml]
+        #[arg(long, short)]
+        file: Option<PathBuf>,
+        /// Only run cases whose name contains this
+        #[arg(long, value_name = "NAME")]
+        filter: Option<String>,
+        /// Refresh every case's cassette from the network instead of replaying it
+        #[arg(long)]
+        record: bool,
+        /// Store the current results as the snapshots instead of comparing them
+        #[arg(long)]
+        update_snapshots: bool,
+    },
+    /// Follow the first results of `discover` or `search` down to their sources
+    Walk(WalkArgs),
+    /// Validate a module and pack it into an archive the app can install
+    Pack {
+        /// Directory of the module
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+        /// Where to write the archive [default: <id>-<version>.module]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Extract a packed module into a directory
+    Unpack {
+        /// The module archive
+        archive: PathBuf,
+        /// Directory to extract into [default: the archive name without extension]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Write into the directory even if it is not empty
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the files of a packed module and check them against its checksums
+    Inspect {
+        /// The module archive
+        archive: PathBuf,
+        /// Print the manifest instead of the file list
+        #[arg(long, conflicts_with = "script")]
+        manifest: bool,
+        /// Print the module script instead of the file list
+        #[arg(long)]
+        script: bool,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        /// The shell to generate completions for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Args)]
+pub struct NewArgs {
+    /// Display name of the module
+    pub name: String,
+    /// The kind of module to generate
+    #[arg(long, short, value_enum, default_value_t = Template::Video)]
+    pub template: Template,
+    /// Directory to create the module in [default: a slug of the name]
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+    /// Module id [default: a slug of the name]
+    #[arg(long)]
+    pub id: Option<String>,
+    /// Author recorded in the manifest [default: `git config user.name` or the
+    /// login name]
+    #[arg(long, env = "CHOUTEN_AUTHOR")]
+    pub author: Option<String>,
+    /// Base url of the site the module scrapes
+    #[arg(long, value_name = "URL", default_value = "https://example.com")]
+    pub base_url: String,
+    /// Write into the directory even if it is not empty
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Subcommand)]
+pub enum RunCommand {
+    /// Call `discover()` and print the sections it returns
+    Discover {
+        #[command(flatten)]
+        options: RunOptions,
+        /// Extra arguments passed after the required ones
+        #[arg(value_name = "ARG")]
+        args: Vec<String>,
+    },
+    /// Call `search(query)` and print the results
+    Search {
+        #[command(flatten)]
+        options: RunOptions,
+        /// The search query
+        query: String,
+        /// Extra arguments passed after the query, e.g. a page number
+        #[arg(value_name = "ARG")]
+        args: Vec<String>,
+    },
+    /// Call `info(url)` and print the details of a title
+    Info {
+        #[command(flatten)]
+        options: RunOptions,
+        /// Url of the title, as returned by `discover` or `search`
+        url: String,
+        /// Extra arguments passed after the url
+        #[arg(value_name = "ARG")]
+        args: Vec<String>,
+    },
+    /// Call `media(url)` and print the episodes or chapters of a title
+    Media {
+        #[command(flatten)]
+        options: RunOptions,
+        /// Url of the title, as passed to `info`
+        url: String,
+        /// Extra arguments passed after the url
+        #[arg(value_name = "ARG")]
+        args: Vec<String>,
+    },
+    /// Call `servers(url)` and print the servers of an episode
+    Servers {
+        #[command(flatten)]
+        options: RunOptions,
+        /// Url of the episode, as returned by `media`
+        url: String,
+        /// Extra arguments passed after the url
+        #[arg(value_name = "ARG")]
+        args: Vec<String>,
+    },
+    /// Call `sources(url)` and print the streams of a server
+    Sources {
+        #[command(flatten)]
+        options: RunOptions,
+        /// Url of the server, as returned by `servers`
+        url: String,
+        /// Extra arguments passed after the url
+        #[arg(value_name = "ARG")]
+        args: Vec<String>,
+        /// Check that every stream and subtitle can be loaded
+        #[arg(long)]
+        probe: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct RunOptions {
+    /// Path to the module script (JavaScript or TypeScript), a module
+    /// directory or a packed module
+    pub filename: PathBuf,
+    /// Parse every argument as JSON instead of passing it as a string
+    #[arg(long, env = "CHOUTEN_JSON")]
+    pub json: bool,
+    /// Seconds to wait for the entry point's promise to settle
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        env = "CHOUTEN_TIMEOUT"
+    )]
+    pub timeout: u64,
+    /// Print the result without checking it against the module result schema
+    #[arg(long)]
+    pub no_validate: bool,
+    /// Whether to load the script as a classic script or an ES module
+    #[arg(long, value_enum, default_value = "auto")]
+    pub module_format: Format,
+    #[command(flatten)]
+    pub cassette: CassetteOptions,
+}
+
+impl RunOptions {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+#[derive(Args)]
+pub struct WalkArgs {
+    /// Path to the module script, a module directory or a packed module
+    #[arg(default_value = ".")]
+    pub module: PathBuf,
+    /// Start from `search(QUERY)` instead of `discover()`
+    #[arg(long, short)]
+    pub query: Option<String>,
+    /// How many entries to follow at every level
+    #[arg(long, short = 'n', value_name = "N", default_value_t = 1)]
+    pub limit: usize,
+    /// Check that every stream and subtitle `sources()` returns can be loaded
+    #[arg(long)]
+    pub probe: bool,
+    /// Seconds to wait for each entry point's promise to settle
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        env = "CHOUTEN_TIMEOUT"
+    )]
+    pub timeout: u64,
+    #[command(flatten)]
+    pub cassette: CassetteOptions,
+}
+
+#[derive(Args)]
+pub struct CassetteOptions {
+    /// Save every request and response into a cassette directory, replacing
+    /// what it held
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Answer requests from a cassette directory instead of the network
+    #[arg(long, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+}
+
+impl CassetteOptions {
+    /// The cassette directory and what to do with it, if one was given.
+    pub fn cassette(&self) -> Option<(&Path, cassette::Mode)> {
+        match (&self.record, &self.replay) {
+            (Some(dir), _) => Some((dir, cassette::Mode::Record)),
+            (None, Some(dir)) => Some((dir, cassette::Mode::Replay)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The entry points a module exposes on its `default` class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Discover,
+    Search,
+    Info,
+    Media,
+    Servers,
+    Sources,
+}
+
+impl Method {
+    pub const ALL: [Method; 6] = [
+        Method::Discover,
+        Method::Search,
+        Method::Info,
+        Method::Media,
+        Method::Servers,
+        Method::Sources,
+    ];
+
+    /// The name of the method on the module instance.
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::Discover => "discover",
+            Method::Search => "search",
+            Method::Info => "info",
+            Method::Media => "media",
+            Method::Servers => "servers",
+            Method::Sources => "sources",
+        }
+    }
+}
+
+/// A fully resolved `chouten run` invocation.
+pub struct Call {
+    pub method: Method,
+    pub options: RunOptions,
+    pub args: Vec<String>,
+    /// Check the streams and subtitles of a `sources()` result.
+    pub probe: bool,
+}
+
+impl From<RunCommand> for Call {
+    fn from(command: RunCommand) -> Call {
+        let probe = matches!(command, RunCommand::Sources { probe: true, .. });
+        let (method, options, args) = match command {
+            RunCommand::Discover { options, args } => (Method::Discover, options, args),
+            RunCommand::Search {
+                options,
+                query,
+                args,
+            } => (Method::Search, options, prepend(query, args)),
+            RunCommand::Info { options, url, args } => (Method::Info, options, prepend(url, args)),
+            RunCommand::Media { options, url, args } => {
+                (Method::Media, options, prepend(url, args))
+            }
+            RunCommand::Servers { options, url, args } => {
+                (Method::Servers, options, prepend(url, args))
+            }
+            RunCommand::Sources {
+                options,
+                url,
+                args,
+                probe: _,
+            } => (Method::Sources, options, prepend(url, args)),
+        };
+
+        Call {
+            method,
+            options,
+            args,
+            probe,
+        }
+    }
+}
+
+fn prepend(first: String, mut rest: Vec<String>) -> Vec<String> {
+    rest.insert(0, first);
+    rest
+}
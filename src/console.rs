@@ -0,0 +1,142 @@
+use crate::source_map::SourceMaps;
+use clap::ValueEnum;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// The least severe console messages that are still printed.
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// How far up the stack to look for the module's frame behind a console call.
+const MAX_FRAMES: usize = 8;
+
+/// Where console messages and diagnostics go. Stdout is left to results.
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+enum Output {
+    Stderr,
+    File(Mutex<File>),
+    Quiet,
+}
+
+/// How severe a console message is, from `console.debug` to `console.error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Level {
+    /// Everything, including `console.debug`, with where each message was logged
+    Debug,
+    /// `console.log`, `console.info` and above
+    Info,
+    /// `console.warn`, `console.trace` and above
+    Warn,
+    /// Only `console.error` and failed assertions
+    Error,
+    /// Nothing
+    Off,
+}
+
+impl Level {
+    fn parse(name: &str) -> Level {
+        match name {
+            "debug" => Level::Debug,
+            "warn" => Level::Warn,
+            "error" => Level::Error,
+            _ => Level::Info,
+        }
+    }
+}
+
+/// Sets the level below which console messages are dropped.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Sends console messages and diagnostics to `log_file` instead of stderr,
+/// or drops them when `quiet` is set.
+pub fn set_output(log_file: Option<&Path>, quiet: bool) -> Result<(), String> {
+    let output = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| format!("Could not open {}: {}", path.display(), err))?;
+            Output::File(Mutex::new(file))
+        }
+        None if quiet => Output::Quiet,
+        None => Output::Stderr,
+    };
+    let _ = OUTPUT.set(output);
+    Ok(())
+}
+
+fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a console message or diagnostic unless its level is filtered out.
+pub fn log(level: Level, message: &str) {
+    if !enabled(level) {
+        return;
+    }
+
+    match OUTPUT.get().unwrap_or(&Output::Stderr) {
+        Output::Stderr => eprintln!("{}", message),
+        Output::File(file) => {
+            let mut file = file.lock().unwrap();
+            let _ = writeln!(file, "{}", message);
+        }
+        Output::Quiet => {}
+    }
+}
+
+/// `__console_print(level, message)`, which `js/console.js` builds `console`
+/// on.
+pub fn print_handler(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    _: v8::ReturnValue,
+) {
+    let level = Level::parse(&args.get(0).to_rust_string_lossy(scope));
+    if !enabled(level) {
+        return;
+    }
+
+    let mut message = args.get(1).to_rust_string_lossy(scope);
+    let source_map = SourceMaps::get(scope);
+    if let Some(source_map) = &source_map {
+        message = source_map.remap(&message);
+    }
+    if enabled(Level::Debug) {
+        if let Some(site) = call_site(scope, source_map.as_ref()) {
+            message = format!("[{}] {}", site, message);
+        }
+    }
+    log(level, &message);
+}
+
+/// Where in the module the console method was called, as `file:line:column`.
+/// The prelude scripts have no name, so the first named frame is the
+/// module's.
+fn call_site(scope: &mut v8::HandleScope, source_map: Option<&SourceMaps>) -> Option<String> {
+    let stack = v8::StackTrace::current_stack_trace(scope, MAX_FRAMES)?;
+    for index in 0..stack.get_frame_count() {
+        let frame = stack.get_frame(scope, index)?;
+        let file = match frame.get_script_name(scope) {
+            Some(name) if name.length() > 0 => name.to_rust_string_lossy(scope),
+            _ => continue,
+        };
+        let line = frame.get_line_number();
+        let column = frame.get_column();
+        return Some(
+            match source_map.and_then(|source_map| source_map.lookup(&file, line, column)) {
+                Some(position) => {
+                    format!("{}:{}:{}", position.file, position.line, position.column)
+                }
+                None => format!("{}:{}:{}", file, line, column),
+            },
+        );
+    }
+    None
+}
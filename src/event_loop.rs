@@ -0,0 +1,311 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// Whether new event loops on this thread skip ahead to their next timer
+    /// when idle.
+    static FAKE_TIME: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes event loops created on this thread from now on run on a virtual
+/// clock: whenever nothing but timers is left, the clock jumps to the next
+/// one instead of sleeping. Host I/O still takes as long as it takes.
+/// Scripts see the same clock through `Date` and `performance`.
+pub fn set_fake_time(enabled: bool) {
+    FAKE_TIME.set(enabled);
+}
+
+/// A unit of work that runs on the V8 thread.
+pub type Task = Box<dyn FnOnce(&mut v8::HandleScope)>;
+
+/// A unit of work produced off the V8 thread, e.g. by host I/O, that is
+/// handed back to the loop once the underlying operation has finished.
+pub type Completion = Box<dyn FnOnce(&mut v8::HandleScope) + Send>;
+
+/// Turns the output of host work into the value a promise settles with:
+/// `Ok` resolves it and `Err` rejects it.
+pub type Settle<T> = for<'a> fn(
+    &mut v8::HandleScope<'a>,
+    T,
+) -> Result<v8::Local<'a, v8::Value>, v8::Local<'a, v8::Value>>;
+
+/// Why driving a promise to completion failed.
+pub enum LoopError<'s> {
+    /// The promise was rejected with the given value.
+    Rejected(v8::Local<'s, v8::Value>),
+    /// The deadline passed while the promise was still pending.
+    TimedOut,
+    /// The promise is still pending but nothing is left that could settle it.
+    Stalled,
+}
+
+struct Timer {
+    deadline: Instant,
+    id: u64,
+    task: Task,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline && self.id == other.id
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    // Reversed so that the `BinaryHeap` pops the earliest deadline first.
+    // Timers with the same deadline fire in the order they were scheduled.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .deadline
+            .cmp(&self.deadline)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+/// The macrotask side of the runtime: ready tasks, timers and completions
+/// coming back from host I/O. Microtasks are owned by V8 and flushed with a
+/// checkpoint between every macrotask.
+///
+/// Host I/O runs as futures on a tokio runtime owned by the loop, so several
+/// operations started by the same script make progress in parallel. The HTTP
+/// client lives next to it because pooled connections are tied to the
+/// runtime that opened them and die with it.
+pub struct EventLoop {
+    tasks: VecDeque<Task>,
+    timers: BinaryHeap<Timer>,
+    next_timer_id: u64,
+    fake_time: bool,
+    /// When the loop was created, which is where `performance.now()` starts.
+    origin: Instant,
+    /// How far the virtual clock is ahead of the real one.
+    skipped: Duration,
+    resolvers: HashMap<u64, v8::Global<v8::PromiseResolver>>,
+    next_op_id: u64,
+    pending_ops: usize,
+    sender: mpsc::Sender<Completion>,
+    receiver: mpsc::Receiver<Completion>,
+    runtime: tokio::runtime::Runtime,
+    client: reqwest::Client,
+}
+
+impl EventLoop {
+    pub fn new() -> EventLoop {
+        let (sender, receiver) = mpsc::channel();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("I/O runtime could not be started");
+
+        EventLoop {
+            tasks: VecDeque::new(),
+            timers: BinaryHeap::new(),
+            next_timer_id: 1,
+            fake_time: FAKE_TIME.get(),
+            origin: Instant::now(),
+            skipped: Duration::ZERO,
+            resolvers: HashMap::new(),
+            next_op_id: 1,
+            pending_ops: 0,
+            sender,
+            receiver,
+            runtime,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Installs a fresh event loop on the isolate.
+    pub fn install(isolate: &mut v8::Isolate) {
+        isolate.set_microtasks_policy(v8::MicrotasksPolicy::Explicit);
+        isolate.set_slot(Rc::new(RefCell::new(EventLoop::new())));
+    }
+
+    /// Returns the event loop installed on the isolate behind `scope`.
+    pub fn get(scope: &mut v8::HandleScope) -> Rc<RefCell<EventLoop>> {
+        scope
+            .get_slot::<Rc<RefCell<EventLoop>>>()
+            .expect("event loop is not installed")
+            .clone()
+    }
+
+    /// The HTTP client for requests made on this loop. Clones share one
+    /// connection pool.
+    pub fn client(&self) -> reqwest::Client {
+        self.client.clone()
+    }
+
+    /// Schedules a task to run once `delay` has elapsed and returns its id.
+    pub fn set_timer(&mut self, delay: Duration, task: Task) -> u64 {
+        let id = self.next_timer_id;
+        self.next_timer_id += 1;
+        self.timers.push(Timer {
+            deadline: self.now() + delay,
+            id,
+            task,
+        });
+        id
+    }
+
+    /// Cancels a timer that has not fired yet.
+    pub fn clear_timer(&mut self, id: u64) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    /// The time on the loop's clock, which runs ahead of the real one when
+    /// time is faked.
+    fn now(&self) -> Instant {
+        Instant::now() + self.skipped
+    }
+
+    /// How much time has passed on the loop's clock since it was created.
+    pub fn elapsed(&self) -> Duration {
+        self.now() - self.origin
+    }
+
+    pub fn fake_time(&self) -> bool {
+        self.fake_time
+    }
+
+    fn has_pending_work(&self) -> bool {
+        !self.tasks.is_empty() || !self.timers.is_empty() || self.pending_ops > 0
+    }
+
+    fn next_task(&mut self) -> Option<Task> {
+        if let Some(task) = self.tasks.pop_front() {
+            return Some(task);
+        }
+
+        while let Ok(completion) = self.receiver.try_recv() {
+            self.pending_ops -= 1;
+            self.tasks.push_back(completion);
+        }
+        if let Some(task) = self.tasks.pop_front() {
+            return Some(task);
+        }
+
+        match self.timers.peek() {
+            Some(timer) if timer.deadline <= self.now() => {
+                self.timers.pop().map(|timer| timer.task)
+            }
+            _ => None,
+        }
+    }
+
+    /// Blocks until a host operation completes or `until` is reached. With a
+    /// fake clock and no host operation running, the clock moves to `until`
+    /// right away.
+    fn wait(&mut self, until: Instant) {
+        let timeout = until.saturating_duration_since(self.now());
+        if self.fake_time && self.pending_ops == 0 {
+            self.skipped += timeout;
+            return;
+        }
+        if let Ok(completion) = self.receiver.recv_timeout(timeout) {
+            self.pending_ops -= 1;
+            self.tasks.push_back(completion);
+        }
+    }
+}
+
+/// Runs `future` on the I/O runtime and returns a promise that is settled
+/// through `settle` on the V8 thread once the future has finished.
+pub fn spawn_promise<'s, F>(
+    scope: &mut v8::HandleScope<'s>,
+    future: F,
+    settle: Settle<F::Output>,
+) -> v8::Local<'s, v8::Promise>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let resolver = v8::PromiseResolver::new(scope).unwrap();
+    let promise = resolver.get_promise(scope);
+    let resolver = v8::Global::new(scope, resolver);
+
+    let event_loop = EventLoop::get(scope);
+    let mut event_loop = event_loop.borrow_mut();
+    let id = event_loop.next_op_id;
+    event_loop.next_op_id += 1;
+    event_loop.resolvers.insert(id, resolver);
+    event_loop.pending_ops += 1;
+
+    // Resolvers are bound to the isolate, so only the op id crosses threads.
+    let sender = event_loop.sender.clone();
+    event_loop.runtime.spawn(async move {
+        let output = future.await;
+        let completion: Completion = Box::new(move |scope: &mut v8::HandleScope| {
+            let resolver = EventLoop::get(scope)
+                .borrow_mut()
+                .resolvers
+                .remove(&id)
+                .expect("op completed twice");
+            let resolver = v8::Local::new(scope, resolver);
+            match settle(scope, output) {
+                Ok(value) => resolver.resolve(scope, value),
+                Err(error) => resolver.reject(scope, error),
+            };
+        });
+        // The receiver only goes away together with the isolate, at which
+        // point nobody is waiting for the result anymore.
+        let _ = sender.send(completion);
+    });
+
+    promise
+}
+
+/// Runs the event loop until `promise` settles or `timeout` elapses.
+///
+/// Microtasks are flushed after every macrotask, so chains of `await`s make
+/// progress the same way they would in a browser.
+pub fn run_until_settled<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    promise: v8::Local<'s, v8::Promise>,
+    timeout: Duration,
+) -> Result<v8::Local<'s, v8::Value>, LoopError<'s>> {
+    let event_loop = EventLoop::get(scope);
+    let deadline = event_loop.borrow().now() + timeout;
+
+    loop {
+        scope.perform_microtask_checkpoint();
+
+        match promise.state() {
+            v8::PromiseState::Fulfilled => return Ok(promise.result(scope)),
+            v8::PromiseState::Rejected => return Err(LoopError::Rejected(promise.result(scope))),
+            v8::PromiseState::Pending => {}
+        }
+
+        // Take the task out before running it so JavaScript is free to
+        // schedule more work on the loop.
+        let task = event_loop.borrow_mut().next_task();
+        if let Some(task) = task {
+            task(scope);
+            continue;
+        }
+
+        if !event_loop.borrow().has_pending_work() {
+            return Err(LoopError::Stalled);
+        }
+        if event_loop.borrow().now() >= deadline {
+            return Err(LoopError::TimedOut);
+        }
+
+        let mut event_loop = event_loop.borrow_mut();
+        let wake_at = match event_loop.timers.peek() {
+            Some(timer) => timer.deadline.min(deadline),
+            None => deadline,
+        };
+        event_loop.wait(wake_at);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::runtime::set_function;
+use ego_tree::{NodeId, NodeRef};
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::{HashMap, HashSet};
+
+/// The documents parsed in an isolate. JavaScript refers to their nodes by
+/// handle, a key into `nodes`, and every node keeps one handle so handles
+/// can be compared.
+///
+/// Every document belongs to a JavaScript wrapper object. Once the wrapper
+/// is garbage collected its document is freed, the next time one is parsed.
+#[derive(Default)]
+struct Dom {
+    documents: HashMap<usize, Document>,
+    nodes: HashMap<u32, (usize, NodeId)>,
+    handles: HashMap<(usize, NodeId), u32>,
+    next_document: usize,
+    next_handle: u32,
+    /// Documents whose wrapper was collected.
+    collected: Vec<usize>,
+}
+
+struct Document {
+    html: Html,
+    /// Keeps the finalizer that frees the document registered.
+    _wrapper: v8::Weak<v8::Object>,
+}
+
+impl Dom {
+    fn handle(&mut self, document: usize, id: NodeId) -> u32 {
+        *self.handles.entry((document, id)).or_insert_with(|| {
+            let handle = self.next_handle;
+            self.next_handle += 1;
+            self.nodes.insert(handle, (document, id));
+            handle
+        })
+    }
+
+    /// Drops the documents whose wrapper was collected, along with the
+    /// handles of their nodes. Handles are never reused.
+    fn free_collected(&mut self) {
+        let collected: HashSet<usize> = self.collected.drain(..).collect();
+        if collected.is_empty() {
+            return;
+        }
+        for document in &collected {
+            self.documents.remove(document);
+        }
+        self.nodes
+            .retain(|_, &mut (document, _)| !collected.contains(&document));
+        self.handles
+            .retain(|&(document, _), _| !collected.contains(&document));
+    }
+}
+
+/// The host functions `js/cheerio.js` builds `cheerio` on, as the object
+/// `__html`. Nodes go in and out as handles; `parse` and `parseFragment`
+/// return the handle of the root.
+pub fn host_object<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Object> {
+    let object = v8::Object::new(scope);
+    set_function(scope, object, "parse", parse);
+    set_function(scope, object, "parseFragment", parse_fragment);
+    set_function(scope, object, "select", select);
+    set_function(scope, object, "matches", matches);
+    set_function(scope, object, "text", text);
+    set_function(scope, object, "html", html);
+    set_function(scope, object, "outerHtml", outer_html);
+    set_function(scope, object, "tag", tag);
+    set_function(scope, object, "attrs", attrs);
+    set_function(scope, object, "parent", parent);
+    set_function(scope, object, "children", children);
+    set_function(scope, object, "next", next);
+    set_function(scope, object, "prev", prev);
+    object
+}
+
+/// `parse(html, wrapper)`: parses a whole document, adding `<html>`,
+/// `<head>` and `<body>` where they are missing, the way browsers do. The
+/// document is freed once `wrapper` is collected.
+fn parse(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let source = args.get(0).to_rust_string_lossy(scope);
+    let document = Html::parse_document(&source);
+    let root = document.tree.root().id();
+    add_document(scope, &args, rv, document, root);
+}
+
+/// `parseFragment(html, wrapper)`: parses a piece of HTML the way it would
+/// be parsed inside `<body>`. Returns the handle of an `<html>` element
+/// holding the parsed nodes.
+fn parse_fragment(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    let source = args.get(0).to_rust_string_lossy(scope);
+    let fragment = Html::parse_fragment(&source);
+    let root = fragment.root_element().id();
+    add_document(scope, &args, rv, fragment, root);
+}
+
+/// Keeps a parsed document until the wrapper object in the second argument
+/// is collected, and returns the handle of `root`.
+fn add_document(
+    scope: &mut v8::HandleScope,
+    args: &v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+    html: Html,
+    root: NodeId,
+) {
+    let wrapper = match v8::Local::<v8::Object>::try_from(args.get(1)) {
+        Ok(wrapper) => wrapper,
+        Err(_) => {
+            let message = v8::String::new(scope, "Expected a wrapper object.").unwrap();
+            let exception = v8::Exception::type_error(scope, message);
+            scope.throw_exception(exception);
+            return;
+        }
+    };
+
+    if scope.get_slot::<Dom>().is_none() {
+        scope.set_slot(Dom::default());
+    }
+    let dom = scope.get_slot_mut::<Dom>().unwrap();
+    dom.free_collected();
+    let document = dom.next_document;
+    dom.next_document += 1;
+
+    // Finalizers run during garbage collection, so the document is only
+    // marked here and dropped on the next parse.
+    let wrapper = v8::Weak::with_finalizer(
+        scope,
+        wrapper,
+        Box::new(move |isolate: &mut v8::Isolate| {
+            if let Some(dom) = isolate.get_slot_mut::<Dom>() {
+                dom.collected.push(document);
+            }
+        }),
+    );
+
+    let dom = scope.get_slot_mut::<Dom>().unwrap();
+    dom.documents.insert(
+        document,
+        Document {
+            html,
+            _wrapper: wrapper,
+        },
+    );
+    let handle = dom.handle(document, root);
+    rv.set_uint32(handle);
+}
+
+/// `select(node, selector)`: the descendants of a node matching a CSS
+/// selector, in document order.
+fn select(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let selector = match selector(scope, &args) {
+        Some(selector) => selector,
+        None => return,
+    };
+    let found = with_node(scope, &args, |node| {
+        node.descendants()
+            .skip(1)
+            .filter_map(ElementRef::wrap)
+            .filter(|element| selector.matches(element))
+            .map(|element| element.id())
+            .collect()
+    });
+    if let Some((document, ids)) = found {
+        return_nodes(scope, rv, document, ids);
+    }
+}
+
+/// `matches(node, selector)`: whether a node is an element matching a CSS
+/// selector.
+fn matches(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let selector = match selector(scope, &args) {
+        Some(selector) => selector,
+        None => return,
+    };
+    let found = with_node(scope, &args, |node| {
+        ElementRef::wrap(node).is_some_and(|element| selector.matches(&element))
+    });
+    if let Some((_, matched)) = found {
+        rv.set_bool(matched);
+    }
+}
+
+/// `text(node)`: the text of a node and all its descendants.
+fn text(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| {
+        node.descendants()
+            .filter_map(|node| node.value().as_text())
+            .map(|text| &**text)
+            .collect::<String>()
+    });
+    if let Some((_, text)) = found {
+        return_string(scope, rv, &text);
+    }
+}
+
+/// `html(node)`: the HTML of a node's children.
+fn html(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| match ElementRef::wrap(node) {
+        Some(element) => element.inner_html(),
+        None => node.children().map(serialize).collect(),
+    });
+    if let Some((_, html)) = found {
+        return_string(scope, rv, &html);
+    }
+}
+
+/// `outerHtml(node)`: the HTML of a node itself.
+fn outer_html(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    rv: v8::ReturnValue,
+) {
+    let found = with_node(scope, &args, |node| match ElementRef::wrap(node) {
+        Some(element) => element.html(),
+        None => node.children().map(serialize).collect(),
+    });
+    if let Some((_, html)) = found {
+        return_string(scope, rv, &html);
+    }
+}
+
+/// `tag(node)`: the lowercase tag name, or `undefined` for the document.
+fn tag(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| {
+        node.value()
+            .as_element()
+            .map(|element| element.name().to_string())
+    });
+    if let Some((_, Some(name))) = found {
+        return_string(scope, rv, &name);
+    }
+}
+
+/// `attrs(node)`: the attributes of an element as an object.
+fn attrs(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let found = with_node(scope, &args, |node| {
+        node.value()
+            .as_element()
+            .map(|element| {
+                element
+                    .attrs()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    });
+    if let Some((_, attributes)) = found {
+        let object = v8::Object::new(scope);
+        for (name, value) in attributes {
+            let name = v8::String::new(scope, &name).unwrap();
+            let value = v8::String::new(scope, &value).unwrap();
+            object.set(scope, name.into(), value.into());
+        }
+        rv.set(object.into());
+    }
+}
+
+/// `parent(node)`: the parent element or document, as a list of at most one.
+fn parent(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| node.parent().map(|parent| parent.id()));
+    if let Some((document, id)) = found {
+        return_nodes(scope, rv, document, id.into_iter().collect());
+    }
+}
+
+/// `children(node)`: the child elements, without text and comments.
+fn children(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| {
+        node.children()
+            .filter(|child| child.value().is_element())
+            .map(|child| child.id())
+            .collect()
+    });
+    if let Some((document, ids)) = found {
+        return_nodes(scope, rv, document, ids);
+    }
+}
+
+/// `next(node)`: the next sibling element, as a list of at most one.
+fn next(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| {
+        node.next_siblings()
+            .find(|sibling| sibling.value().is_element())
+            .map(|sibling| sibling.id())
+    });
+    if let Some((document, id)) = found {
+        return_nodes(scope, rv, document, id.into_iter().collect());
+    }
+}
+
+/// `prev(node)`: the previous sibling element, as a list of at most one.
+fn prev(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, rv: v8::ReturnValue) {
+    let found = with_node(scope, &args, |node| {
+        node.prev_siblings()
+            .find(|sibling| sibling.value().is_element())
+            .map(|sibling| sibling.id())
+    });
+    if let Some((document, id)) = found {
+        return_nodes(scope, rv, document, id.into_iter().collect());
+    }
+}
+
+/// Serializes a child of the document, which has no `ElementRef` of its own.
+fn serialize(node: NodeRef<Node>) -> String {
+    match node.value() {
+        Node::Text(text) => text.to_string(),
+        Node::Comment(comment) => format!("<!--{}-->", comment.comment),
+        Node::Doctype(doctype) => format!("<!DOCTYPE {}>", doctype.name()),
+        _ => ElementRef::wrap(node)
+            .map(|element| element.html())
+            .unwrap_or_default(),
+    }
+}
+
+/// Parses the second argument as a CSS selector, throwing a `SyntaxError`
+/// when it is not one.
+fn selector(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments) -> Option<Selector> {
+    let source = args.get(1).to_rust_string_lossy(scope);
+    let message = match Selector::parse(&source) {
+        Ok(selector) => return Some(selector),
+        Err(err) => format!("Invalid selector {:?}: {}", source, err),
+    };
+    let message = v8::String::new(scope, &message).unwrap();
+    let exception = v8::Exception::syntax_error(scope, message);
+    scope.throw_exception(exception);
+    None
+}
+
+/// Runs `f` on the node whose handle is the first argument and returns the
+/// node's document along with the result. Throws a `TypeError` when the
+/// argument is not a handle.
+fn with_node<T>(
+    scope: &mut v8::HandleScope,
+    args: &v8::FunctionCallbackArguments,
+    f: impl FnOnce(NodeRef<Node>) -> T,
+) -> Option<(usize, T)> {
+    let handle = args.get(0).uint32_value(scope);
+    let found = scope.get_slot::<Dom>().and_then(|dom| {
+        let &(document, id) = dom.nodes.get(&handle?)?;
+        let node = dom.documents.get(&document)?.html.tree.get(id)?;
+        Some((document, f(node)))
+    });
+
+    if found.is_none() {
+        let message = v8::String::new(scope, "Not a parsed HTML node.").unwrap();
+        let exception = v8::Exception::type_error(scope, message);
+        scope.throw_exception(exception);
+    }
+    found
+}
+
+fn return_nodes(
+    scope: &mut v8::HandleScope,
+    mut rv: v8::ReturnValue,
+    document: usize,
+    ids: Vec<NodeId>,
+) {
+    let dom = scope.get_slot_mut::<Dom>().unwrap();
+    let handles: Vec<u32> = ids.into_iter().map(|id| dom.handle(document, id)).collect();
+    let elements: Vec<_> = handles
+        .into_iter()
+        .map(|handle| v8::Integer::new_from_unsigned(scope, handle).into())
+        .collect();
+    rv.set(v8::Array::new_with_elements(scope, &elements).into());
+}
+
+fn return_string(scope: &mut v8::HandleScope, mut rv: v8::ReturnValue, string: &str) {
+    let string = v8::String::new(scope, string).unwrap();
+    rv.set(string.into());
+}
@@ -0,0 +1,481 @@
+use crate::cassette::{Cassette, Entry, Mode};
+use crate::console::{self, Level};
+use crate::event_loop::{self, EventLoop};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Method;
+use std::collections::HashMap;
+
+/// Everything `request(url, method, headers, body)` forwards to the client.
+#[derive(Debug)]
+pub struct Request {
+    pub url: String,
+    pub method: Method,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+}
+
+pub fn send_request_handler<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    args: v8::FunctionCallbackArguments<'s>,
+    mut return_value: v8::ReturnValue,
+) {
+    let request = match read_request(scope, &args) {
+        Ok(request) => request,
+        Err(ArgumentError::Invalid(message)) => {
+            let message = v8::String::new(scope, &message).unwrap();
+            let exception = v8::Exception::type_error(scope, message);
+            scope.throw_exception(exception);
+            return;
+        }
+        // The exception is already pending and reaches the script as is.
+        Err(ArgumentError::Threw) => return,
+    };
+
+    // Counted here rather than on the I/O runtime, where requests made
+    // together may start in any order.
+    let entry = Cassette::get(scope).map(|cassette| cassette.start(&request));
+
+    // The request runs on the I/O runtime while the script keeps going
+    let client = EventLoop::get(scope).borrow().client();
+    let promise = event_loop::spawn_promise(
+        scope,
+        send_request_async(client, request, entry),
+        settle_response,
+    );
+
+    // Set the return value of the JavaScript function
+    return_value.set(promise.into());
+}
+
+fn settle_response<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    response: Result<Response, String>,
+) -> Result<v8::Local<'a, v8::Value>, v8::Local<'a, v8::Value>> {
+    match response {
+        Ok(response) => {
+            // Create the JavaScript object representing the response
+            let v8_response = create_v8_response_object(scope, &response);
+            Ok(v8_response.into())
+        }
+        // Like `fetch`, failing to get any response at all is a `TypeError`.
+        Err(message) => {
+            let message = v8::String::new(scope, &message).unwrap();
+            Err(v8::Exception::type_error(scope, message))
+        }
+    }
+}
+
+/// Why the arguments of `request` could not be read.
+enum ArgumentError {
+    /// The arguments are not a valid request, thrown as a `TypeError`.
+    Invalid(String),
+    /// Reading them ran script, such as a getter, that threw. The exception
+    /// is still pending.
+    Threw,
+}
+
+impl From<String> for ArgumentError {
+    fn from(message: String) -> ArgumentError {
+        ArgumentError::Invalid(message)
+    }
+}
+
+/// Converts a value to a string the way `String(value)` does.
+fn to_string(
+    scope: &mut v8::HandleScope,
+    value: v8::Local<v8::Value>,
+) -> Result<String, ArgumentError> {
+    let string = value.to_string(scope).ok_or(ArgumentError::Threw)?;
+    Ok(string.to_rust_string_lossy(scope))
+}
+
+/// Reads an object's own enumerable properties as key and value pairs.
+fn entries<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    object: v8::Local<'s, v8::Object>,
+) -> Result<Vec<(String, v8::Local<'s, v8::Value>)>, ArgumentError> {
+    let names = object
+        .get_own_property_names(scope, Default::default())
+        .ok_or(ArgumentError::Threw)?;
+    let mut entries = Vec::new();
+    for index in 0..names.length() {
+        let key = names.get_index(scope, index).ok_or(ArgumentError::Threw)?;
+        let value = object.get(scope, key).ok_or(ArgumentError::Threw)?;
+        entries.push((to_string(scope, key)?, value));
+    }
+    Ok(entries)
+}
+
+fn read_request<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    args: &v8::FunctionCallbackArguments<'s>,
+) -> Result<Request, ArgumentError> {
+    let url = to_string(scope, args.get(0))?;
+
+    let method = if args.get(1).is_null_or_undefined() {
+        Method::GET
+    } else {
+        let method = to_string(scope, args.get(1))?;
+        parse_method(&method).ok_or_else(|| format!("Unsupported method: {}", method))?
+    };
+
+    let mut headers = read_headers(scope, args.get(2))?;
+    let body = read_body(scope, args.get(3), &mut headers)?;
+
+    if body.is_some() && (method == Method::GET || method == Method::HEAD) {
+        return Err(format!("Request with {} method cannot have a body.", method).into());
+    }
+
+    Ok(Request {
+        url,
+        method,
+        headers,
+        body,
+    })
+}
+
+fn parse_method(method: &str) -> Option<Method> {
+    match method.to_ascii_uppercase().as_str() {
+        "GET" => Some(Method::GET),
+        "POST" => Some(Method::POST),
+        "PUT" => Some(Method::PUT),
+        "PATCH" => Some(Method::PATCH),
+        "DELETE" => Some(Method::DELETE),
+        "HEAD" => Some(Method::HEAD),
+        "OPTIONS" => Some(Method::OPTIONS),
+        _ => None,
+    }
+}
+
+/// Reads a plain `{ name: value }` object into a header map.
+fn read_headers<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+) -> Result<HeaderMap, ArgumentError> {
+    let mut headers = HeaderMap::new();
+    if value.is_null_or_undefined() {
+        return Ok(headers);
+    }
+
+    let object = v8::Local::<v8::Object>::try_from(value)
+        .map_err(|_| "Headers must be an object of name/value pairs.".to_string())?;
+
+    for (name, value) in entries(scope, object)? {
+        let value = to_string(scope, value)?;
+
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name: {}", name))?;
+        let value = HeaderValue::from_str(&value)
+            .map_err(|_| format!("Invalid value for header {}: {}", name, value))?;
+        headers.append(name, value);
+    }
+
+    Ok(headers)
+}
+
+/// Converts the body argument into bytes.
+///
+/// Strings and typed arrays are sent as they are. Other objects are encoded
+/// as a form when the content type asks for one and as JSON otherwise, in
+/// which case a missing content type is filled in.
+fn read_body<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    headers: &mut HeaderMap,
+) -> Result<Option<Vec<u8>>, ArgumentError> {
+    if value.is_null_or_undefined() {
+        return Ok(None);
+    }
+
+    if value.is_string() {
+        return Ok(Some(to_string(scope, value)?.into_bytes()));
+    }
+
+    if let Ok(buffer) = v8::Local::<v8::ArrayBuffer>::try_from(value) {
+        let view = v8::Uint8Array::new(scope, buffer, 0, buffer.byte_length()).unwrap();
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Ok(Some(bytes));
+    }
+
+    if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(value) {
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Ok(Some(bytes));
+    }
+
+    let object = match v8::Local::<v8::Object>::try_from(value) {
+        Ok(object) => object,
+        Err(_) => return Ok(Some(to_string(scope, value)?.into_bytes())),
+    };
+
+    let is_form = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if is_form {
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        for (name, value) in entries(scope, object)? {
+            form.append_pair(&name, &to_string(scope, value)?);
+        }
+        return Ok(Some(form.finish().into_bytes()));
+    }
+
+    // Stringifying throws for cycles and BigInts, with a better message
+    // than one made up here.
+    let json = v8::json::stringify(scope, value).ok_or(ArgumentError::Threw)?;
+    if !headers.contains_key(CONTENT_TYPE) {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    }
+    Ok(Some(json.to_rust_string_lossy(scope).into_bytes()))
+}
+
+/// Sends the request, or answers it from the cassette when one is replayed.
+///
+/// Replay misses and requests that get no response at all, e.g. because
+/// the host does not resolve or the TLS handshake fails, reject the promise.
+/// Any HTTP status, including server errors, resolves it.
+async fn send_request_async(
+    client: reqwest::Client,
+    request: Request,
+    entry: Option<Entry>,
+) -> Result<Response, String> {
+    let entry = match entry {
+        Some(entry) if entry.mode() == Mode::Replay => {
+            let response = entry.replay(&request);
+            if let Err(err) = &response {
+                console::log(Level::Warn, err);
+            }
+            return response;
+        }
+        entry => entry,
+    };
+
+    let mut builder = client
+        .request(request.method.clone(), &request.url)
+        .headers(request.headers.clone());
+    if let Some(body) = &request.body {
+        builder = builder.body(body.clone());
+    }
+
+    let response = match builder.send().await {
+        Ok(response) => Response::read(response).await,
+        Err(err) => {
+            return Err(format!(
+                "Request to {} failed: {}",
+                request.url,
+                describe(&err)
+            ))
+        }
+    };
+
+    if let Some(entry) = entry {
+        if let Err(err) = entry.record(&request, &response) {
+            console::log(Level::Warn, &err);
+        }
+    }
+    Ok(response)
+}
+
+/// Describes a request error along with its causes, which say what actually
+/// went wrong, e.g. the DNS lookup or the certificate check.
+fn describe(err: &reqwest::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = std::error::Error::source(cause);
+    }
+    message
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status_code: i32,
+    pub status_text: String,
+    /// The body as received, which need not be text.
+    pub body: Vec<u8>,
+    pub content_type: String,
+    pub headers: HashMap<String, String>,
+}
+
+impl Response {
+    /// Reads the status, headers and body of a response. Repeated headers
+    /// are joined with commas.
+    pub async fn read(response: reqwest::Response) -> Response {
+        let status_code = response.status().as_u16() as i32;
+        let status_text = response
+            .status()
+            .canonical_reason()
+            .unwrap_or("")
+            .to_string();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for (key, value) in response.headers().iter() {
+            let key_string = key.to_string();
+            let value_string = value.to_str().unwrap_or("");
+            headers
+                .entry(key_string)
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(value_string);
+                })
+                .or_insert_with(|| value_string.to_string());
+        }
+
+        // Now extract the body after all headers and status code are extracted
+        let body = response
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .unwrap_or_default();
+
+        Response {
+            status_code,
+            status_text,
+            body,
+            content_type,
+            headers,
+        }
+    }
+
+    /// The body decoded as UTF-8, with invalid sequences replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+fn create_v8_response_object<'a>(
+    scope: &mut v8::HandleScope<'a>,
+    response: &Response,
+) -> v8::Local<'a, v8::Object> {
+    // Create a function template for the Response class
+    let response_template = v8::FunctionTemplate::new(scope, response_constructor);
+
+    // Get the function constructor from the template
+    let constructor = response_template.get_function(scope).unwrap();
+
+    // Create an empty object instance for the Response class
+    let obj = constructor.new_instance(scope, &[]).unwrap();
+
+    // Set properties on the instance
+    let status_code_key = v8::String::new(scope, "statusCode").unwrap();
+    let status_code_value = v8::Integer::new(scope, response.status_code);
+    obj.set(scope, status_code_key.into(), status_code_value.into());
+
+    let status_text_key = v8::String::new(scope, "statusText").unwrap();
+    let status_text_value = v8::String::new(scope, &response.status_text).unwrap();
+    obj.set(scope, status_text_key.into(), status_text_value.into());
+
+    // `body` stays text for modules that call `request` directly; `fetch`
+    // reads the bytes and only decodes them in `text()` and `json()`.
+    let body_key = v8::String::new(scope, "body").unwrap();
+    let body_value = v8::String::new(scope, &response.text()).unwrap();
+    obj.set(scope, body_key.into(), body_value.into());
+
+    let bytes_key = v8::String::new(scope, "bytes").unwrap();
+    let length = response.body.len();
+    let store = v8::ArrayBuffer::new_backing_store_from_vec(response.body.clone()).make_shared();
+    let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+    let bytes_value = v8::Uint8Array::new(scope, buffer, 0, length).unwrap();
+    obj.set(scope, bytes_key.into(), bytes_value.into());
+
+    let content_type_key = v8::String::new(scope, "contentType").unwrap();
+    let content_type_value = v8::String::new(scope, &response.content_type).unwrap();
+    obj.set(scope, content_type_key.into(), content_type_value.into());
+
+    let headers_key = v8::String::new(scope, "headers").unwrap();
+    let headers_obj = v8::Object::new(scope);
+    for (key, value) in &response.headers {
+        let v8_key = v8::String::new(scope, key).unwrap();
+        let v8_value = v8::String::new(scope, value).unwrap();
+        headers_obj.set(scope, v8_key.into(), v8_value.into());
+    }
+    obj.set(scope, headers_key.into(), headers_obj.into());
+
+    obj
+}
+
+// Constructor for the Response class
+fn response_constructor(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut return_value: v8::ReturnValue,
+) {
+    // Create a new JavaScript object instance
+    let obj = v8::Object::new(scope);
+
+    // Set properties on the instance
+    let status_code_key = v8::String::new(scope, "statusCode").unwrap();
+    obj.set(scope, status_code_key.into(), args.get(0));
+
+    let body_key = v8::String::new(scope, "body").unwrap();
+    obj.set(scope, body_key.into(), args.get(1));
+
+    let content_type_key = v8::String::new(scope, "contentType").unwrap();
+    obj.set(scope, content_type_key.into(), args.get(2));
+
+    let headers_key = v8::String::new(scope, "headers").unwrap();
+    obj.set(scope, headers_key.into(), args.get(3));
+
+    // Set the return value to the created object
+    return_value.set(obj.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers one request with `body` and returns the url to request.
+    fn serve_once(body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/key", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn keeps_binary_bodies_as_they_are() {
+        // Not valid UTF-8, so decoding the body anywhere would change it.
+        let body: &'static [u8] = &[0x00, 0xff, 0xfe, 0x80, 0xc3, 0x28, 0x7f];
+        let request = Request {
+            url: serve_once(body),
+            method: Method::GET,
+            headers: HeaderMap::new(),
+            body: None,
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime
+            .block_on(send_request_async(reqwest::Client::new(), request, None))
+            .unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.content_type, "application/octet-stream");
+        assert_eq!(response.body, body);
+        assert_eq!(response.text(), "\0\u{fffd}\u{fffd}\u{fffd}\u{fffd}(\u{7f}");
+    }
+}
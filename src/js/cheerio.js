@@ -0,0 +1,328 @@
+// A cheerio-like `cheerio.load(html)` on top of the host `__html` object,
+// which parses documents in Rust and hands out nodes as numeric handles.
+((globalThis) => {
+  const dom = globalThis.__html;
+  delete globalThis.__html;
+
+  // The elements of one parsed document or fragment, one object per handle
+  // so elements can be compared with `===`. Every element refers back to
+  // it, and the host frees the parsed document once none are reachable.
+  class Document {
+    constructor(html, fragment) {
+      this.elements = new Map();
+      const parse = fragment ? dom.parseFragment : dom.parse;
+      this.root = this.element(parse(String(html), this));
+    }
+
+    element(handle) {
+      if (handle === undefined) {
+        return null;
+      }
+      let node = this.elements.get(handle);
+      if (!node) {
+        node = new Element(this, handle);
+        this.elements.set(handle, node);
+      }
+      return node;
+    }
+  }
+
+  // An element in the shape of cheerio's, with the common properties
+  // computed on access.
+  class Element {
+    #document;
+
+    constructor(document, handle) {
+      this.#document = document;
+      this.handle = handle;
+    }
+
+    // The elements of the same document behind `handles`.
+    _elements(handles) {
+      return handles.map((handle) => this.#document.element(handle));
+    }
+
+    get type() {
+      return this.name === undefined ? "root" : "tag";
+    }
+
+    get name() {
+      return dom.tag(this.handle);
+    }
+
+    get tagName() {
+      return this.name;
+    }
+
+    get attribs() {
+      return dom.attrs(this.handle);
+    }
+
+    get parent() {
+      return this._elements(dom.parent(this.handle))[0] || null;
+    }
+
+    get children() {
+      return this._elements(dom.children(this.handle));
+    }
+
+    get next() {
+      return this._elements(dom.next(this.handle))[0] || null;
+    }
+
+    get prev() {
+      return this._elements(dom.prev(this.handle))[0] || null;
+    }
+  }
+
+  // Keeps the first occurrence of every element.
+  const unique = (nodes) => [...new Set(nodes)];
+
+  // Turns a selector or a predicate called like `fn.call(el, i, el)` into
+  // a test on elements.
+  const predicate = (test) => {
+    if (typeof test === "function") {
+      return (node, index) => Boolean(test.call(node, index, node));
+    }
+    if (test instanceof Cheerio) {
+      return (node) => test.toArray().includes(node);
+    }
+    return (node) => node instanceof Element && dom.matches(node.handle, String(test));
+  };
+
+  class Cheerio {
+    constructor(nodes, root) {
+      this.length = nodes.length;
+      nodes.forEach((node, index) => {
+        this[index] = node;
+      });
+      this._root = root;
+    }
+
+    _wrap(nodes) {
+      return new Cheerio(nodes, this._root);
+    }
+
+    // Applies `step` to every element and collects the resulting elements,
+    // optionally keeping only those matching `selector`.
+    _traverse(step, selector) {
+      let nodes = unique(
+        this.toArray()
+          .filter((node) => node instanceof Element)
+          .flatMap((node) => node._elements(step(node.handle)))
+      );
+      if (selector !== undefined) {
+        nodes = nodes.filter(predicate(selector));
+      }
+      return this._wrap(nodes);
+    }
+
+    toArray() {
+      return Array.prototype.slice.call(this);
+    }
+
+    get(index) {
+      if (index === undefined) {
+        return this.toArray();
+      }
+      return this[index < 0 ? this.length + index : index];
+    }
+
+    eq(index) {
+      const node = this.get(index);
+      return this._wrap(node === undefined ? [] : [node]);
+    }
+
+    first() {
+      return this.eq(0);
+    }
+
+    last() {
+      return this.eq(-1);
+    }
+
+    slice(start, end) {
+      return this._wrap(this.toArray().slice(start, end));
+    }
+
+    each(callback) {
+      const nodes = this.toArray();
+      for (let index = 0; index < nodes.length; index++) {
+        if (callback.call(nodes[index], index, nodes[index]) === false) {
+          break;
+        }
+      }
+      return this;
+    }
+
+    map(callback) {
+      const values = [];
+      this.toArray().forEach((node, index) => {
+        const value = callback.call(node, index, node);
+        if (Array.isArray(value)) {
+          values.push(...value);
+        } else if (value !== null && value !== undefined) {
+          values.push(value);
+        }
+      });
+      return this._wrap(values);
+    }
+
+    filter(test) {
+      return this._wrap(this.toArray().filter(predicate(test)));
+    }
+
+    not(test) {
+      const matches = predicate(test);
+      return this._wrap(this.toArray().filter((node, index) => !matches(node, index)));
+    }
+
+    is(test) {
+      return this.toArray().some(predicate(test));
+    }
+
+    find(selector) {
+      return this._traverse((handle) => dom.select(handle, String(selector)));
+    }
+
+    children(selector) {
+      return this._traverse(dom.children, selector);
+    }
+
+    parent(selector) {
+      return this._traverse(dom.parent, selector).filter((index, node) => node.type === "tag");
+    }
+
+    parents(selector) {
+      const ancestors = (handle) => {
+        const found = [];
+        for (let [parent] = dom.parent(handle); parent !== undefined; [parent] = dom.parent(parent)) {
+          if (dom.tag(parent) !== undefined) {
+            found.push(parent);
+          }
+        }
+        return found;
+      };
+      return this._traverse(ancestors, selector);
+    }
+
+    closest(selector) {
+      const closest = (handle) => {
+        for (let current = handle; current !== undefined; [current] = dom.parent(current)) {
+          if (dom.matches(current, String(selector))) {
+            return [current];
+          }
+        }
+        return [];
+      };
+      return this._traverse(closest);
+    }
+
+    next(selector) {
+      return this._traverse(dom.next, selector);
+    }
+
+    prev(selector) {
+      return this._traverse(dom.prev, selector);
+    }
+
+    siblings(selector) {
+      const siblings = (handle) => {
+        const [parent] = dom.parent(handle);
+        if (parent === undefined) {
+          return [];
+        }
+        return dom.children(parent).filter((child) => child !== handle);
+      };
+      return this._traverse(siblings, selector);
+    }
+
+    text() {
+      return this.toArray()
+        .map((node) => (node instanceof Element ? dom.text(node.handle) : String(node)))
+        .join("");
+    }
+
+    html() {
+      const node = this[0];
+      return node instanceof Element ? dom.html(node.handle) : null;
+    }
+
+    attr(name) {
+      const node = this[0];
+      if (!(node instanceof Element) || node.type !== "tag") {
+        return undefined;
+      }
+      const attributes = dom.attrs(node.handle);
+      if (name === undefined) {
+        return attributes;
+      }
+      return Object.prototype.hasOwnProperty.call(attributes, name) ? attributes[name] : undefined;
+    }
+
+    data(name) {
+      return this.attr(`data-${name}`);
+    }
+
+    val() {
+      return this.attr("value");
+    }
+
+    hasClass(name) {
+      return this.toArray().some(
+        (node) =>
+          node instanceof Element &&
+          (dom.attrs(node.handle).class || "").split(/\s+/).includes(name)
+      );
+    }
+
+    toString() {
+      return this.toArray()
+        .map((node) => (node instanceof Element ? dom.outerHtml(node.handle) : String(node)))
+        .join("");
+    }
+  }
+
+  // Whether `$` is given markup rather than a selector.
+  const isHtml = (selector) => {
+    const trimmed = selector.trim();
+    return trimmed.startsWith("<") && trimmed.endsWith(">");
+  };
+
+  // Returns a `$` bound to a parsed document, like cheerio's.
+  const load = (html) => {
+    const root = new Document(html, false).root;
+    const rootSelection = new Cheerio([root], root);
+
+    const $ = (selector, context) => {
+      if (selector === undefined || selector === null || selector === "") {
+        return new Cheerio([], root);
+      }
+      // Markup is parsed into new nodes, like `$("<div>...</div>")` in
+      // cheerio.
+      if (typeof selector === "string" && isHtml(selector)) {
+        return new Cheerio(new Document(selector, true).root.children, root);
+      }
+      if (selector instanceof Cheerio) {
+        return selector;
+      }
+      if (selector instanceof Element) {
+        return new Cheerio([selector], root);
+      }
+      if (Array.isArray(selector)) {
+        return new Cheerio(selector.filter((node) => node instanceof Element), root);
+      }
+      const scope = context === undefined ? rootSelection : $(context);
+      return scope.find(selector);
+    };
+
+    $.root = () => rootSelection;
+    $.html = (selector) =>
+      selector === undefined ? rootSelection.html() : $(selector).toString();
+    $.text = (selector) =>
+      selector === undefined ? rootSelection.text() : $(selector).text();
+    return $;
+  };
+
+  globalThis.cheerio = { load };
+})(globalThis);
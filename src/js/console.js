@@ -0,0 +1,338 @@
+// `console` on top of the host `__console_print(level, message)` function,
+// which filters by level and writes the message to stderr or the log file.
+((globalThis) => {
+  const print = globalThis.__console_print;
+  delete globalThis.__console_print;
+
+  // How deep nested objects are shown before they are abbreviated.
+  const MAX_DEPTH = 2;
+  // Longest array or set shown in full.
+  const MAX_ITEMS = 100;
+  // Entries are put on one line while it stays this short.
+  const LINE_WIDTH = 72;
+
+  const IDENTIFIER = /^[A-Za-z_$][A-Za-z0-9_$]*$/;
+
+  const quote = (string) =>
+    "'" + string.replace(/\\/g, "\\\\").replace(/'/g, "\\'").replace(/\n/g, "\\n") + "'";
+
+  const formatKey = (key) => {
+    if (typeof key === "symbol") {
+      return `[${key.toString()}]`;
+    }
+    return IDENTIFIER.test(key) ? key : quote(key);
+  };
+
+  const constructorName = (value) => {
+    try {
+      const prototype = Object.getPrototypeOf(value);
+      if (prototype === null) {
+        return "[Object: null prototype]";
+      }
+      return prototype.constructor && prototype.constructor.name;
+    } catch {
+      return undefined;
+    }
+  };
+
+  // Joins entries on one line when they fit and one per line otherwise.
+  const wrap = (prefix, open, entries, close, indent) => {
+    if (entries.length === 0) {
+      return `${prefix}${open}${close}`;
+    }
+    const line = `${prefix}${open} ${entries.join(", ")} ${close}`;
+    if (line.length <= LINE_WIDTH && !line.includes("\n")) {
+      return line;
+    }
+    const inner = "  ".repeat(indent + 1);
+    const outer = "  ".repeat(indent);
+    return `${prefix}${open}\n${inner}${entries.join(`,\n${inner}`)}\n${outer}${close}`;
+  };
+
+  const inspect = (value, depth = 0, seen = []) => {
+    switch (typeof value) {
+      case "string":
+        return depth === 0 ? value : quote(value);
+      case "number":
+        return Object.is(value, -0) ? "-0" : String(value);
+      case "bigint":
+        return `${value}n`;
+      case "symbol":
+        return value.toString();
+      case "undefined":
+        return "undefined";
+      case "boolean":
+        return String(value);
+      case "function": {
+        const source = Function.prototype.toString.call(value);
+        if (source.startsWith("class")) {
+          return `[class ${value.name || "(anonymous)"}]`;
+        }
+        return value.name ? `[Function: ${value.name}]` : "[Function (anonymous)]";
+      }
+    }
+
+    if (value === null) {
+      return "null";
+    }
+    if (seen.includes(value)) {
+      return "[Circular]";
+    }
+
+    if (value instanceof Error) {
+      return value.stack || `${value.name}: ${value.message}`;
+    }
+    if (value instanceof Date) {
+      return Number.isNaN(value.getTime()) ? "Invalid Date" : value.toISOString();
+    }
+    if (value instanceof RegExp) {
+      return value.toString();
+    }
+    if (value instanceof Promise) {
+      return "Promise { <unknown> }";
+    }
+    if (value instanceof WeakMap || value instanceof WeakSet) {
+      return `${constructorName(value)} { <items unknown> }`;
+    }
+
+    const name = constructorName(value);
+    const nested = seen.concat([value]);
+    const child = (item) => inspect(item, depth + 1, nested);
+
+    if (Array.isArray(value) || ArrayBuffer.isView(value)) {
+      if (depth > MAX_DEPTH) {
+        return `[${Array.isArray(value) ? "Array" : name}]`;
+      }
+      const items = Array.prototype.slice.call(value, 0, MAX_ITEMS).map(child);
+      if (value.length > MAX_ITEMS) {
+        items.push(`... ${value.length - MAX_ITEMS} more items`);
+      }
+      const prefix = name === "Array" ? "" : `${name}(${value.length}) `;
+      return wrap(prefix, "[", items, "]", depth);
+    }
+    if (value instanceof Map) {
+      if (depth > MAX_DEPTH) {
+        return "[Map]";
+      }
+      const entries = [...value].map(([key, item]) => `${child(key)} => ${child(item)}`);
+      return wrap(`Map(${value.size}) `, "{", entries, "}", depth);
+    }
+    if (value instanceof Set) {
+      if (depth > MAX_DEPTH) {
+        return "[Set]";
+      }
+      const items = [...value].slice(0, MAX_ITEMS).map(child);
+      return wrap(`Set(${value.size}) `, "{", items, "}", depth);
+    }
+    if (value instanceof ArrayBuffer) {
+      return `ArrayBuffer { byteLength: ${value.byteLength} }`;
+    }
+
+    if (depth > MAX_DEPTH) {
+      return name && name !== "Object" ? `[${name}]` : "[Object]";
+    }
+    const keys = [
+      ...Object.keys(value),
+      ...Object.getOwnPropertySymbols(value).filter((symbol) =>
+        Object.prototype.propertyIsEnumerable.call(value, symbol)
+      ),
+    ];
+    const entries = keys.map((key) => {
+      let item;
+      try {
+        item = value[key];
+      } catch (error) {
+        item = error;
+      }
+      return `${formatKey(key)}: ${child(item)}`;
+    });
+    const prefix = name && name !== "Object" ? `${name} ` : "";
+    return wrap(prefix, "{", entries, "}", depth);
+  };
+
+  // Applies `%s`, `%d`, `%i`, `%f`, `%o`, `%O`, `%j` and `%c` in a leading
+  // format string and appends the remaining arguments.
+  const format = (args) => {
+    if (args.length === 0) {
+      return "";
+    }
+
+    const parts = [];
+    let rest = args;
+    if (typeof args[0] === "string" && args[0].includes("%")) {
+      let index = 1;
+      const formatted = args[0].replace(/%([sdifoOjc%])/g, (match, kind) => {
+        if (kind === "%") {
+          return "%";
+        }
+        if (index >= args.length) {
+          return match;
+        }
+        const arg = args[index++];
+        switch (kind) {
+          case "s":
+            return typeof arg === "string" ? arg : inspect(arg, 1);
+          case "d":
+          case "i": {
+            if (typeof arg === "bigint") {
+              return `${arg}n`;
+            }
+            const number = Number(arg);
+            return String(kind === "i" ? Math.trunc(number) : number);
+          }
+          case "f":
+            return String(parseFloat(arg));
+          case "j":
+            try {
+              return JSON.stringify(arg);
+            } catch {
+              return "[Circular]";
+            }
+          case "c":
+            return "";
+          default:
+            return inspect(arg, 1);
+        }
+      });
+      parts.push(formatted);
+      rest = args.slice(index);
+    } else {
+      parts.push(inspect(args[0]));
+      rest = args.slice(1);
+    }
+
+    for (const arg of rest) {
+      parts.push(inspect(arg));
+    }
+    return parts.join(" ");
+  };
+
+  let groupIndent = "";
+  const write = (level, message) => {
+    if (groupIndent) {
+      message = message
+        .split("\n")
+        .map((line) => groupIndent + line)
+        .join("\n");
+    }
+    print(level, message);
+  };
+
+  // Renders rows of objects or arrays as a box drawn table.
+  const table = (data, columns) => {
+    if (data === null || typeof data !== "object") {
+      return inspect(data);
+    }
+
+    const rows = data instanceof Map ? [...data] : Object.entries(data);
+    const header = ["(index)"];
+    const valuesColumn = "Values";
+    let hasValues = false;
+    const cells = rows.map(([index, row]) => {
+      const cell = { "(index)": String(index) };
+      if (row !== null && typeof row === "object") {
+        for (const key of Object.keys(row)) {
+          if (columns && !columns.includes(key)) {
+            continue;
+          }
+          if (!header.includes(key)) {
+            header.push(key);
+          }
+          cell[key] = inspect(row[key], 1);
+        }
+      } else {
+        hasValues = true;
+        cell[valuesColumn] = inspect(row, 1);
+      }
+      return cell;
+    });
+    if (hasValues) {
+      header.push(valuesColumn);
+    }
+
+    const widths = header.map((key) =>
+      Math.max(key.length, ...cells.map((cell) => (cell[key] || "").length))
+    );
+    const line = (left, middle, right) =>
+      left + widths.map((width) => "─".repeat(width + 2)).join(middle) + right;
+    const row = (values) =>
+      "│" +
+      values.map((value, column) => ` ${value.padEnd(widths[column])} `).join("│") +
+      "│";
+
+    return [
+      line("┌", "┬", "┐"),
+      row(header),
+      line("├", "┼", "┤"),
+      ...cells.map((cell) => row(header.map((key) => cell[key] || ""))),
+      line("└", "┴", "┘"),
+    ].join("\n");
+  };
+
+  const timers = new Map();
+  const counts = new Map();
+  const elapsed = (label) => `${label}: ${(Date.now() - timers.get(label)).toFixed(0)}ms`;
+
+  const console = {
+    log: (...args) => write("info", format(args)),
+    info: (...args) => write("info", format(args)),
+    debug: (...args) => write("debug", format(args)),
+    warn: (...args) => write("warn", format(args)),
+    error: (...args) => write("error", format(args)),
+    trace: (...args) => {
+      const stack = (new Error().stack || "").split("\n").slice(2).join("\n");
+      write("warn", `Trace: ${format(args)}\n${stack}`);
+    },
+    assert: (condition, ...args) => {
+      if (!condition) {
+        const message = format(args);
+        write("error", message ? `Assertion failed: ${message}` : "Assertion failed");
+      }
+    },
+    dir: (value) => write("info", inspect(value, 1)),
+    table: (data, columns) => write("info", table(data, columns)),
+    time: (label = "default") => {
+      if (timers.has(label)) {
+        write("warn", `Timer '${label}' already exists`);
+        return;
+      }
+      timers.set(label, Date.now());
+    },
+    timeLog: (label = "default", ...args) => {
+      if (!timers.has(label)) {
+        write("warn", `Timer '${label}' does not exist`);
+        return;
+      }
+      write("info", [elapsed(label), ...args.map((arg) => inspect(arg))].join(" "));
+    },
+    timeEnd: (label = "default") => {
+      if (!timers.has(label)) {
+        write("warn", `Timer '${label}' does not exist`);
+        return;
+      }
+      write("info", elapsed(label));
+      timers.delete(label);
+    },
+    count: (label = "default") => {
+      const count = (counts.get(label) || 0) + 1;
+      counts.set(label, count);
+      write("info", `${label}: ${count}`);
+    },
+    countReset: (label = "default") => {
+      counts.delete(label);
+    },
+    group: (...args) => {
+      if (args.length > 0) {
+        write("info", format(args));
+      }
+      groupIndent += "  ";
+    },
+    groupEnd: () => {
+      groupIndent = groupIndent.slice(2);
+    },
+  };
+  console.groupCollapsed = console.group;
+  console.dirxml = console.log;
+
+  globalThis.console = console;
+})(globalThis);
@@ -0,0 +1,228 @@
+// `fetch`, `Headers`, `Request` and `Response` on top of the host `request`
+// function, so modules written against the standard API run unchanged.
+((globalThis) => {
+  const request = globalThis.request;
+  // Captured like `request`, in case a script replaces the globals.
+  const { TextDecoder, TextEncoder, URLSearchParams } = globalThis;
+
+  const normalizeMethod = (method) => {
+    const upper = String(method).toUpperCase();
+    const known = ["DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "POST", "PUT"];
+    return known.includes(upper) ? upper : String(method);
+  };
+
+  class Headers {
+    #map = new Map();
+
+    constructor(init = undefined) {
+      if (init === undefined || init === null) {
+        return;
+      }
+      if (init instanceof Headers) {
+        init.forEach((value, name) => this.append(name, value));
+      } else if (typeof init[Symbol.iterator] === "function") {
+        for (const pair of init) {
+          const [name, value] = Array.from(pair);
+          this.append(name, value);
+        }
+      } else {
+        for (const name of Object.keys(init)) {
+          this.append(name, init[name]);
+        }
+      }
+    }
+
+    append(name, value) {
+      const key = String(name).toLowerCase();
+      const current = this.#map.get(key);
+      this.#map.set(key, current === undefined ? String(value) : `${current}, ${value}`);
+    }
+
+    delete(name) {
+      this.#map.delete(String(name).toLowerCase());
+    }
+
+    get(name) {
+      const value = this.#map.get(String(name).toLowerCase());
+      return value === undefined ? null : value;
+    }
+
+    has(name) {
+      return this.#map.has(String(name).toLowerCase());
+    }
+
+    set(name, value) {
+      this.#map.set(String(name).toLowerCase(), String(value));
+    }
+
+    forEach(callback, thisArg = undefined) {
+      for (const [name, value] of this) {
+        callback.call(thisArg, value, name, this);
+      }
+    }
+
+    *entries() {
+      const names = Array.from(this.#map.keys()).sort();
+      for (const name of names) {
+        yield [name, this.#map.get(name)];
+      }
+    }
+
+    *keys() {
+      for (const [name] of this.entries()) {
+        yield name;
+      }
+    }
+
+    *values() {
+      for (const [, value] of this.entries()) {
+        yield value;
+      }
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+  }
+
+  // Turns a body init into a string or a copy of its bytes, along with the
+  // content type it implies, if any.
+  const extractBody = (body) => {
+    if (body === undefined || body === null) {
+      return [null, null];
+    }
+    if (body instanceof ArrayBuffer) {
+      return [new Uint8Array(body.slice(0)), null];
+    }
+    if (ArrayBuffer.isView(body)) {
+      const end = body.byteOffset + body.byteLength;
+      return [new Uint8Array(body.buffer.slice(body.byteOffset, end)), null];
+    }
+    if (body instanceof URLSearchParams) {
+      return [body.toString(), "application/x-www-form-urlencoded;charset=UTF-8"];
+    }
+    return [String(body), null];
+  };
+
+  class Body {
+    #body;
+    #used = false;
+
+    // Fills in the content type the body implies unless `headers` has one.
+    constructor(body, headers) {
+      const [extracted, type] = extractBody(body);
+      this.#body = extracted;
+      if (type !== null && !headers.has("content-type")) {
+        headers.set("content-type", type);
+      }
+    }
+
+    get body() {
+      return this.#body;
+    }
+
+    get bodyUsed() {
+      return this.#used;
+    }
+
+    #consume() {
+      if (this.#used) {
+        throw new TypeError("Body has already been consumed.");
+      }
+      this.#used = true;
+      return this.#body;
+    }
+
+    async arrayBuffer() {
+      const body = this.#consume();
+      if (body === null) {
+        return new ArrayBuffer(0);
+      }
+      const bytes = typeof body === "string" ? new TextEncoder().encode(body) : body;
+      return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+    }
+
+    async text() {
+      const body = this.#consume();
+      if (body === null) {
+        return "";
+      }
+      return typeof body === "string" ? body : new TextDecoder().decode(body);
+    }
+
+    async json() {
+      return JSON.parse(await this.text());
+    }
+  }
+
+  class Request extends Body {
+    constructor(input, init = {}) {
+      const source = input instanceof Request ? input : null;
+      const body = init.body !== undefined ? init.body : source ? source.body : null;
+      const headers = new Headers(init.headers || (source ? source.headers : undefined));
+      super(body, headers);
+
+      this.url = source ? source.url : String(input);
+      this.method = normalizeMethod(init.method || (source ? source.method : "GET"));
+      this.headers = headers;
+
+      if ((this.method === "GET" || this.method === "HEAD") && this.body !== null) {
+        throw new TypeError(`Request with ${this.method} method cannot have a body.`);
+      }
+    }
+
+    clone() {
+      return new Request(this);
+    }
+  }
+
+  class Response extends Body {
+    constructor(body = null, init = {}) {
+      const headers = new Headers(init.headers);
+      super(body, headers);
+
+      this.status = init.status === undefined ? 200 : init.status;
+      this.statusText = init.statusText === undefined ? "" : String(init.statusText);
+      this.headers = headers;
+      this.url = init.url === undefined ? "" : String(init.url);
+      this.redirected = false;
+      this.type = "basic";
+    }
+
+    get ok() {
+      return this.status >= 200 && this.status < 300;
+    }
+
+    clone() {
+      return new Response(this.body, this);
+    }
+
+    static json(data, init = {}) {
+      const headers = new Headers(init.headers);
+      if (!headers.has("content-type")) {
+        headers.set("content-type", "application/json");
+      }
+      return new Response(JSON.stringify(data), { ...init, headers });
+    }
+  }
+
+  async function fetch(input, init = {}) {
+    const req = new Request(input, init);
+
+    const headers = {};
+    req.headers.forEach((value, name) => {
+      headers[name] = value;
+    });
+
+    const res = await request(req.url, req.method, headers, req.body);
+
+    return new Response(res.bytes, {
+      status: res.statusCode,
+      statusText: res.statusText,
+      headers: res.headers,
+      url: req.url,
+    });
+  }
+
+  Object.assign(globalThis, { fetch, Headers, Request, Response });
+})(globalThis);
@@ -0,0 +1,121 @@
+// `setTimeout`, `setInterval`, `clearTimeout`, `clearInterval`,
+// `queueMicrotask`, `reportError` and `performance` on top of the host
+// `__timers` object, which schedules callbacks on the event loop. With fake
+// time, `Date` is replaced to follow the event loop's virtual clock too.
+((globalThis) => {
+  const timers = globalThis.__timers;
+  delete globalThis.__timers;
+
+  // The ids handed to scripts, mapped to the host timer currently scheduled
+  // for them. An interval gets a new host timer every time it fires.
+  const active = new Map();
+  let nextId = 1;
+
+  const checkCallback = (callback) => {
+    if (typeof callback !== "function") {
+      throw new TypeError("The callback must be a function.");
+    }
+  };
+
+  const start = (callback, delay, args, repeat) => {
+    checkCallback(callback);
+    const id = nextId++;
+    const fire = () => {
+      if (repeat) {
+        active.set(id, timers.schedule(fire, delay));
+      } else {
+        active.delete(id);
+      }
+      try {
+        callback(...args);
+      } catch (error) {
+        timers.report(error);
+      }
+    };
+    active.set(id, timers.schedule(fire, delay));
+    return id;
+  };
+
+  const clear = (id) => {
+    const timer = active.get(id);
+    if (timer !== undefined) {
+      active.delete(id);
+      timers.cancel(timer);
+    }
+  };
+
+  function setTimeout(callback, delay = 0, ...args) {
+    return start(callback, delay, args, false);
+  }
+
+  function setInterval(callback, delay = 0, ...args) {
+    return start(callback, delay, args, true);
+  }
+
+  function clearTimeout(id) {
+    clear(id);
+  }
+
+  function clearInterval(id) {
+    clear(id);
+  }
+
+  function queueMicrotask(callback) {
+    checkCallback(callback);
+    timers.enqueueMicrotask(() => {
+      try {
+        callback();
+      } catch (error) {
+        timers.report(error);
+      }
+    });
+  }
+
+  function reportError(error) {
+    timers.report(error);
+  }
+
+  // Where the event loop's clock started, in wall-clock time.
+  const timeOrigin = Date.now() - timers.now();
+
+  const performance = {
+    timeOrigin,
+    now() {
+      return timers.now();
+    },
+  };
+
+  if (timers.fakeTime) {
+    const RealDate = globalThis.Date;
+    const now = () => Math.floor(timeOrigin + timers.now());
+
+    // Only the current time changes. Dates built from explicit values, the
+    // static methods and the prototype are the real ones.
+    const FakeDate = function Date(...args) {
+      if (new.target === undefined) {
+        return new RealDate(now()).toString();
+      }
+      return Reflect.construct(RealDate, args.length === 0 ? [now()] : args, new.target);
+    };
+    Object.setPrototypeOf(FakeDate, RealDate);
+    Object.defineProperty(FakeDate, "length", { value: RealDate.length });
+    FakeDate.prototype = RealDate.prototype;
+    FakeDate.now = now;
+    Object.defineProperty(RealDate.prototype, "constructor", {
+      value: FakeDate,
+      writable: true,
+      configurable: true,
+    });
+    globalThis.Date = FakeDate;
+  }
+
+  Object.assign(globalThis, {
+    performance,
+    setTimeout,
+    setInterval,
+    clearTimeout,
+    clearInterval,
+    queueMicrotask,
+    reportError,
+  });
+})(globalThis);
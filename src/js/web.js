@@ -0,0 +1,481 @@
+// `URL`, `URLSearchParams`, `TextEncoder`, `TextDecoder`, `atob` and `btoa`
+// on top of the host `__web` object, which does the parsing and encoding in
+// Rust.
+((globalThis) => {
+  const web = globalThis.__web;
+  delete globalThis.__web;
+
+  const codes = {
+    IndexSizeError: 1,
+    HierarchyRequestError: 3,
+    WrongDocumentError: 4,
+    InvalidCharacterError: 5,
+    NoModificationAllowedError: 7,
+    NotFoundError: 8,
+    NotSupportedError: 9,
+    InvalidStateError: 11,
+    SyntaxError: 12,
+    InvalidModificationError: 13,
+    NamespaceError: 14,
+    InvalidAccessError: 15,
+    TypeMismatchError: 17,
+    SecurityError: 18,
+    NetworkError: 19,
+    AbortError: 20,
+    URLMismatchError: 21,
+    QuotaExceededError: 22,
+    TimeoutError: 23,
+    InvalidNodeTypeError: 24,
+    DataCloneError: 25,
+  };
+
+  class DOMException extends Error {
+    #name;
+
+    constructor(message = "", name = "Error") {
+      super(message);
+      this.#name = String(name);
+    }
+
+    get name() {
+      return this.#name;
+    }
+
+    get code() {
+      return codes[this.#name] || 0;
+    }
+  }
+
+  // Updates the list of a `URLSearchParams` without notifying its URL.
+  let replaceList;
+
+  // The URL each `URLSearchParams` from `url.searchParams` writes back to.
+  const owners = new WeakMap();
+
+  class URLSearchParams {
+    #list = [];
+
+    static {
+      replaceList = (params, list) => {
+        params.#list = list;
+      };
+    }
+
+    constructor(init = "") {
+      if (init instanceof URLSearchParams) {
+        this.#list = init.#list.map(([name, value]) => [name, value]);
+      } else if (typeof init === "object" && init !== null) {
+        if (typeof init[Symbol.iterator] === "function") {
+          for (const pair of init) {
+            const values = Array.from(pair);
+            if (values.length !== 2) {
+              throw new TypeError("Each query pair must be a name and a value.");
+            }
+            this.#list.push([String(values[0]), String(values[1])]);
+          }
+        } else {
+          for (const name of Object.keys(init)) {
+            this.#list.push([name, String(init[name])]);
+          }
+        }
+      } else {
+        const query = String(init);
+        this.#list = web.parseQuery(query.startsWith("?") ? query.slice(1) : query);
+      }
+    }
+
+    #update() {
+      const update = owners.get(this);
+      if (update) {
+        update(this.toString());
+      }
+    }
+
+    get size() {
+      return this.#list.length;
+    }
+
+    append(name, value) {
+      this.#list.push([String(name), String(value)]);
+      this.#update();
+    }
+
+    delete(name, value = undefined) {
+      name = String(name);
+      value = value === undefined ? undefined : String(value);
+      this.#list = this.#list.filter(
+        (pair) => pair[0] !== name || (value !== undefined && pair[1] !== value)
+      );
+      this.#update();
+    }
+
+    get(name) {
+      name = String(name);
+      const pair = this.#list.find((pair) => pair[0] === name);
+      return pair === undefined ? null : pair[1];
+    }
+
+    getAll(name) {
+      name = String(name);
+      return this.#list.filter((pair) => pair[0] === name).map((pair) => pair[1]);
+    }
+
+    has(name, value = undefined) {
+      name = String(name);
+      value = value === undefined ? undefined : String(value);
+      return this.#list.some((pair) => pair[0] === name && (value === undefined || pair[1] === value));
+    }
+
+    set(name, value) {
+      name = String(name);
+      value = String(value);
+      const index = this.#list.findIndex((pair) => pair[0] === name);
+      if (index === -1) {
+        this.#list.push([name, value]);
+      } else {
+        this.#list[index] = [name, value];
+        this.#list = this.#list.filter((pair, other) => other <= index || pair[0] !== name);
+      }
+      this.#update();
+    }
+
+    // Stable, and compares names by UTF-16 code units like the spec asks.
+    sort() {
+      this.#list.sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+      this.#update();
+    }
+
+    forEach(callback, thisArg = undefined) {
+      for (const [name, value] of this) {
+        callback.call(thisArg, value, name, this);
+      }
+    }
+
+    *entries() {
+      for (let index = 0; index < this.#list.length; index++) {
+        const [name, value] = this.#list[index];
+        yield [name, value];
+      }
+    }
+
+    *keys() {
+      for (const [name] of this.entries()) {
+        yield name;
+      }
+    }
+
+    *values() {
+      for (const [, value] of this.entries()) {
+        yield value;
+      }
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+
+    toString() {
+      return web.serializeQuery(this.#list);
+    }
+  }
+
+  class URL {
+    #components;
+    #searchParams;
+
+    constructor(url, base = undefined) {
+      const components = web.parseUrl(String(url), base === undefined ? undefined : String(base));
+      if (components === null) {
+        const described = base === undefined ? url : `${url} against ${base}`;
+        throw new TypeError(`Invalid URL: ${described}`);
+      }
+      this.#components = components;
+      this.#searchParams = new URLSearchParams(components.search);
+      owners.set(this.#searchParams, (query) => {
+        this.#components = web.setUrl(this.href, "search", query);
+      });
+    }
+
+    static canParse(url, base = undefined) {
+      return web.parseUrl(String(url), base === undefined ? undefined : String(base)) !== null;
+    }
+
+    static parse(url, base = undefined) {
+      return URL.canParse(url, base) ? new URL(url, base) : null;
+    }
+
+    #set(name, value) {
+      const components = web.setUrl(this.href, name, String(value));
+      if (components === null) {
+        throw new TypeError(`Invalid URL: ${value}`);
+      }
+      this.#components = components;
+      if (name === "href" || name === "search") {
+        replaceList(this.#searchParams, web.parseQuery(components.search.slice(1)));
+      }
+    }
+
+    get href() {
+      return this.#components.href;
+    }
+
+    set href(value) {
+      this.#set("href", value);
+    }
+
+    get origin() {
+      return this.#components.origin;
+    }
+
+    get protocol() {
+      return this.#components.protocol;
+    }
+
+    set protocol(value) {
+      this.#set("protocol", value);
+    }
+
+    get username() {
+      return this.#components.username;
+    }
+
+    set username(value) {
+      this.#set("username", value);
+    }
+
+    get password() {
+      return this.#components.password;
+    }
+
+    set password(value) {
+      this.#set("password", value);
+    }
+
+    get host() {
+      return this.#components.host;
+    }
+
+    set host(value) {
+      this.#set("host", value);
+    }
+
+    get hostname() {
+      return this.#components.hostname;
+    }
+
+    set hostname(value) {
+      this.#set("hostname", value);
+    }
+
+    get port() {
+      return this.#components.port;
+    }
+
+    set port(value) {
+      this.#set("port", value);
+    }
+
+    get pathname() {
+      return this.#components.pathname;
+    }
+
+    set pathname(value) {
+      this.#set("pathname", value);
+    }
+
+    get search() {
+      return this.#components.search;
+    }
+
+    set search(value) {
+      this.#set("search", value);
+    }
+
+    get searchParams() {
+      return this.#searchParams;
+    }
+
+    get hash() {
+      return this.#components.hash;
+    }
+
+    set hash(value) {
+      this.#set("hash", value);
+    }
+
+    toString() {
+      return this.href;
+    }
+
+    toJSON() {
+      return this.href;
+    }
+  }
+
+  class TextEncoder {
+    get encoding() {
+      return "utf-8";
+    }
+
+    encode(input = "") {
+      return web.encodeUtf8(String(input));
+    }
+
+    // Writes as many whole characters as fit into `destination`.
+    encodeInto(source, destination) {
+      let read = 0;
+      let written = 0;
+      for (const char of String(source)) {
+        let point = char.codePointAt(0);
+        if (point >= 0xd800 && point <= 0xdfff) {
+          point = 0xfffd;
+        }
+
+        let bytes;
+        if (point < 0x80) {
+          bytes = [point];
+        } else if (point < 0x800) {
+          bytes = [0xc0 | (point >> 6), 0x80 | (point & 0x3f)];
+        } else if (point < 0x10000) {
+          bytes = [0xe0 | (point >> 12), 0x80 | ((point >> 6) & 0x3f), 0x80 | (point & 0x3f)];
+        } else {
+          bytes = [
+            0xf0 | (point >> 18),
+            0x80 | ((point >> 12) & 0x3f),
+            0x80 | ((point >> 6) & 0x3f),
+            0x80 | (point & 0x3f),
+          ];
+        }
+
+        if (written + bytes.length > destination.length) {
+          break;
+        }
+        destination.set(bytes, written);
+        read += char.length;
+        written += bytes.length;
+      }
+      return { read, written };
+    }
+  }
+
+  // The labels the Encoding spec gives UTF-8, the only encoding supported.
+  const utf8Labels = [
+    "unicode-1-1-utf-8",
+    "unicode11utf8",
+    "unicode20utf8",
+    "utf-8",
+    "utf8",
+    "x-unicode20utf8",
+  ];
+
+  const toBytes = (input) => {
+    if (input === undefined) {
+      return new Uint8Array(0);
+    }
+    if (input instanceof ArrayBuffer) {
+      return new Uint8Array(input);
+    }
+    if (ArrayBuffer.isView(input)) {
+      return new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+    }
+    throw new TypeError("The input must be an ArrayBuffer or an ArrayBufferView.");
+  };
+
+  class TextDecoder {
+    #fatal;
+    #ignoreBOM;
+    // Bytes of an incomplete character held back while streaming.
+    #pending = new Uint8Array(0);
+    #bomSeen = false;
+
+    constructor(label = "utf-8", options = {}) {
+      const encoding = String(label).trim().toLowerCase();
+      if (!utf8Labels.includes(encoding)) {
+        throw new RangeError(`The encoding label "${label}" is not supported.`);
+      }
+      this.#fatal = Boolean(options.fatal);
+      this.#ignoreBOM = Boolean(options.ignoreBOM);
+    }
+
+    get encoding() {
+      return "utf-8";
+    }
+
+    get fatal() {
+      return this.#fatal;
+    }
+
+    get ignoreBOM() {
+      return this.#ignoreBOM;
+    }
+
+    decode(input = undefined, options = {}) {
+      const stream = Boolean(options.stream);
+      let bytes = toBytes(input);
+      if (this.#pending.length > 0) {
+        const joined = new Uint8Array(this.#pending.length + bytes.length);
+        joined.set(this.#pending);
+        joined.set(bytes, this.#pending.length);
+        bytes = joined;
+      }
+
+      const decoded = web.decodeUtf8(bytes, this.#fatal, stream);
+      if (decoded === null) {
+        this.#pending = new Uint8Array(0);
+        this.#bomSeen = false;
+        throw new TypeError("The encoded data was not valid UTF-8.");
+      }
+
+      let [text, pending] = decoded;
+      this.#pending = bytes.slice(bytes.length - pending);
+      if (!this.#bomSeen && text.length > 0) {
+        if (!this.#ignoreBOM && text.charCodeAt(0) === 0xfeff) {
+          text = text.slice(1);
+        }
+        this.#bomSeen = true;
+      }
+      if (!stream) {
+        this.#bomSeen = false;
+      }
+      return text;
+    }
+  }
+
+  function atob(data) {
+    if (arguments.length === 0) {
+      throw new TypeError("atob requires 1 argument.");
+    }
+    const decoded = web.atob(String(data));
+    if (decoded === null) {
+      throw new DOMException(
+        "The string to be decoded is not correctly encoded.",
+        "InvalidCharacterError"
+      );
+    }
+    return decoded;
+  }
+
+  function btoa(data) {
+    if (arguments.length === 0) {
+      throw new TypeError("btoa requires 1 argument.");
+    }
+    const encoded = web.btoa(String(data));
+    if (encoded === null) {
+      throw new DOMException(
+        "The string to be encoded contains characters outside of the Latin1 range.",
+        "InvalidCharacterError"
+      );
+    }
+    return encoded;
+  }
+
+  Object.assign(globalThis, {
+    DOMException,
+    URL,
+    URLSearchParams,
+    TextEncoder,
+    TextDecoder,
+    atob,
+    btoa,
+  });
+})(globalThis);
@@ -0,0 +1,447 @@
+use crate::archive;
+use crate::cli::Method;
+use crate::pack;
+use crate::runtime;
+use crate::schema::Violation;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Name of the manifest inside a module directory.
+pub const MANIFEST_FILE: &str = "metadata.json";
+
+/// Icons are shown as squares in the app and are rejected outside this range.
+const ICON_MIN_SIZE: u32 = 128;
+const ICON_MAX_SIZE: u32 = 1024;
+const ICON_MAX_BYTES: u64 = 1024 * 1024;
+
+/// The kind of content a module provides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleType {
+    Video,
+    Manga,
+    Novel,
+}
+
+/// The metadata a module ships in `metadata.json`.
+///
+/// Every field has a default so a manifest with missing fields still parses
+/// and `validate` can report all of them at once.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Manifest {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    pub icon: String,
+    pub script: String,
+    pub language: String,
+    #[serde(rename = "type")]
+    pub module_type: Option<ModuleType>,
+    pub base_url: Option<String>,
+    /// Entry point names, kept as written so unknown ones are reported by
+    /// `validate` rather than failing the parse.
+    pub features: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the module in `dir`.
+    pub fn load(dir: &Path) -> Result<Manifest, String> {
+        let path = dir.join(MANIFEST_FILE);
+        let content = fs::read_to_string(&path)
+            .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        Manifest::parse(&content).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(content: &str) -> Result<Manifest, String> {
+        serde_json::from_str(content).map_err(|err| err.to_string())
+    }
+}
+
+/// Checks the module in `dir`: the manifest fields, the icon and that the
+/// script defines every entry point the manifest declares.
+pub fn validate(dir: &Path) -> Result<(Manifest, Vec<Violation>), String> {
+    let manifest = Manifest::load(dir)?;
+    let mut violations = Vec::new();
+    let mut violation = |field: &str, message: String| {
+        violations.push(Violation {
+            path: format!("$.{}", field),
+            message,
+        })
+    };
+
+    for (field, value) in [
+        ("id", &manifest.id),
+        ("name", &manifest.name),
+        ("version", &manifest.version),
+        ("author", &manifest.author),
+        ("icon", &manifest.icon),
+        ("script", &manifest.script),
+        ("language", &manifest.language),
+    ] {
+        if value.trim().is_empty() {
+            violation(field, "required field is missing".to_string());
+        }
+    }
+    if manifest.module_type.is_none() {
+        violation("type", "required field is missing".to_string());
+    }
+    if manifest.features.is_empty() {
+        violation(
+            "features",
+            "at least one entry point must be declared".to_string(),
+        );
+    }
+
+    if !manifest.id.is_empty() && !is_valid_id(&manifest.id) {
+        violation(
+            "id",
+            format!(
+                "expected lowercase letters and digits separated by '.', '-' or '_', got {:?}",
+                manifest.id
+            ),
+        );
+    }
+    if !manifest.version.is_empty() {
+        if let Err(err) = semver::Version::parse(&manifest.version) {
+            violation(
+                "version",
+                format!(
+                    "expected a semantic version ({}), got {:?}",
+                    err, manifest.version
+                ),
+            );
+        }
+    }
+    if !manifest.language.is_empty() && !is_valid_language(&manifest.language) {
+        violation(
+            "language",
+            format!(
+                "expected a language tag like \"en\" or \"pt-BR\", got {:?}",
+                manifest.language
+            ),
+        );
+    }
+    if let Some(base_url) = &manifest.base_url {
+        match url::Url::parse(base_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            _ => violation(
+                "baseUrl",
+                format!("expected an http(s) url, got {:?}", base_url),
+            ),
+        }
+    }
+    for (index, feature) in manifest.features.iter().enumerate() {
+        if parse_method(feature).is_none() {
+            let names: Vec<_> = Method::ALL.iter().map(|method| method.name()).collect();
+            violation(
+                &format!("features[{}]", index),
+                format!("expected one of {}, got {:?}", names.join(", "), feature),
+            );
+        } else if manifest.features[..index].contains(feature) {
+            violation(
+                &format!("features[{}]", index),
+                format!("{} is declared more than once", feature),
+            );
+        }
+    }
+
+    if !manifest.icon.is_empty() {
+        // Checked before joining, so the icon cannot point anywhere on disk.
+        match pack::normalise(Path::new(&manifest.icon)) {
+            Some(icon) => {
+                if let Err(message) = check_icon(&dir.join(icon)) {
+                    violation("icon", message);
+                }
+            }
+            None => violation(
+                "icon",
+                format!(
+                    "expected a path inside the module directory, got {:?}",
+                    manifest.icon
+                ),
+            ),
+        }
+    }
+
+    // Like the icon, the script is checked before joining: `defined_methods`
+    // runs it.
+    let script = match pack::normalise(Path::new(&manifest.script)) {
+        Some(script) => Some(script),
+        None if manifest.script.is_empty() => None,
+        None => {
+            violation(
+                "script",
+                format!(
+                    "expected a path inside the module directory, got {:?}",
+                    manifest.script
+                ),
+            );
+            None
+        }
+    };
+    if let Some(script) = script {
+        match archive::read_script(dir, &script) {
+            Ok(script) => match runtime::defined_methods(&script) {
+                Ok(defined) => {
+                    for (index, feature) in manifest.features.iter().enumerate() {
+                        match parse_method(feature) {
+                            Some(method) if !defined.contains(&method) => violation(
+                                &format!("features[{}]", index),
+                                format!("script does not define a `{}` method", feature),
+                            ),
+                            _ => {}
+                        }
+                    }
+                }
+                Err(err) => violation("script", err.to_string()),
+            },
+            Err(err) => violation("script", err),
+        }
+    }
+
+    Ok((manifest, violations))
+}
+
+fn parse_method(name: &str) -> Option<Method> {
+    Method::ALL.into_iter().find(|method| method.name() == name)
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.split(['.', '-', '_']).all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    })
+}
+
+fn is_valid_language(language: &str) -> bool {
+    let mut parts = language.split('-');
+    let primary = parts.next().unwrap_or("");
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_lowercase())
+        && parts.all(|part| {
+            (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// Checks that the icon is a square PNG of a reasonable size.
+fn check_icon(path: &Path) -> Result<(), String> {
+    let metadata =
+        fs::metadata(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    if metadata.len() > ICON_MAX_BYTES {
+        return Err(format!(
+            "icon is {} bytes, at most {} are allowed",
+            metadata.len(),
+            ICON_MAX_BYTES
+        ));
+    }
+
+    let bytes =
+        fs::read(path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let (width, height) =
+        png_dimensions(&bytes).ok_or_else(|| "icon is not a PNG image".to_string())?;
+
+    if width != height {
+        return Err(format!("icon must be square, got {}x{}", width, height));
+    }
+    if !(ICON_MIN_SIZE..=ICON_MAX_SIZE).contains(&width) {
+        return Err(format!(
+            "icon must be between {0}x{0} and {1}x{1}, got {2}x{2}",
+            ICON_MIN_SIZE, ICON_MAX_SIZE, width
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the width and height from the IHDR chunk of a PNG.
+fn png_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if bytes.len() < 24 || !bytes.starts_with(SIGNATURE) || &bytes[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(bytes[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(bytes[20..24].try_into().unwrap());
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// A PNG header, which is all `check_icon` reads.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        bytes.extend(width.to_be_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes
+    }
+
+    /// Writes a module with the given manifest and icon into a fresh
+    /// directory and validates it.
+    fn validate_module(name: &str, manifest: serde_json::Value, icon: &[u8]) -> Vec<Violation> {
+        let dir =
+            std::env::temp_dir().join(format!("chouten-manifest-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), manifest.to_string()).unwrap();
+        fs::write(dir.join("icon.png"), icon).unwrap();
+
+        let result = validate(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap().1
+    }
+
+    /// The violations of a module without a script, leaving out the
+    /// script's.
+    fn violations(name: &str, manifest: serde_json::Value, icon: &[u8]) -> Vec<String> {
+        validate_module(name, manifest, icon)
+            .into_iter()
+            .filter(|violation| violation.path != "$.script")
+            .map(|violation| violation.to_string())
+            .collect()
+    }
+
+    fn valid() -> serde_json::Value {
+        json!({
+            "id": "com.example.module",
+            "name": "Example",
+            "version": "1.0.0",
+            "author": "someone",
+            "icon": "icon.png",
+            "script": "code.js",
+            "language": "pt-BR",
+            "type": "video",
+            "baseUrl": "https://example.com",
+            "features": ["search", "info"],
+        })
+    }
+
+    #[test]
+    fn accepts_a_valid_manifest() {
+        assert!(violations("valid", valid(), &png(256, 256)).is_empty());
+    }
+
+    #[test]
+    fn reports_missing_fields() {
+        let found = violations("empty", json!({}), &png(256, 256));
+        assert_eq!(
+            found,
+            [
+                "$.id: required field is missing",
+                "$.name: required field is missing",
+                "$.version: required field is missing",
+                "$.author: required field is missing",
+                "$.icon: required field is missing",
+                "$.language: required field is missing",
+                "$.type: required field is missing",
+                "$.features: at least one entry point must be declared",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_field_formats() {
+        let mut manifest = valid();
+        manifest["id"] = json!("Com.Example..module");
+        manifest["version"] = json!("1.0");
+        manifest["language"] = json!("english");
+        manifest["baseUrl"] = json!("ftp://example.com");
+        let paths: Vec<_> = violations("formats", manifest, &png(256, 256))
+            .into_iter()
+            .map(|violation| violation.split(':').next().unwrap().to_string())
+            .collect();
+        assert_eq!(paths, ["$.id", "$.version", "$.language", "$.baseUrl"]);
+    }
+
+    #[test]
+    fn reports_unknown_and_repeated_features() {
+        let mut manifest = valid();
+        manifest["features"] = json!(["search", "download", "search"]);
+        assert_eq!(
+            violations("features", manifest, &png(256, 256)),
+            [
+                "$.features[1]: expected one of discover, search, info, media, servers, sources, got \"download\"",
+                "$.features[2]: search is declared more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_the_icon() {
+        let found = violations("wide", valid(), &png(256, 128));
+        assert_eq!(found, ["$.icon: icon must be square, got 256x128"]);
+
+        let found = violations("small", valid(), &png(64, 64));
+        assert_eq!(
+            found,
+            ["$.icon: icon must be between 128x128 and 1024x1024, got 64x64"]
+        );
+
+        let found = violations("jpeg", valid(), b"\xff\xd8\xff\xe0");
+        assert_eq!(found, ["$.icon: icon is not a PNG image"]);
+    }
+
+    #[test]
+    fn keeps_the_icon_inside_the_module() {
+        for icon in ["../icon.png", "/etc/passwd"] {
+            let mut manifest = valid();
+            manifest["icon"] = json!(icon);
+            assert_eq!(
+                violations("outside", manifest, &png(256, 256)),
+                [format!(
+                    "$.icon: expected a path inside the module directory, got {:?}",
+                    icon
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_script_inside_the_module() {
+        for script in ["../code.js", "/tmp/code.js"] {
+            let mut manifest = valid();
+            manifest["script"] = json!(script);
+            let violations: Vec<_> = validate_module("script", manifest, &png(256, 256))
+                .into_iter()
+                .map(|violation| violation.to_string())
+                .collect();
+            assert_eq!(
+                violations,
+                [format!(
+                    "$.script: expected a path inside the module directory, got {:?}",
+                    script
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn validates_ids_and_languages() {
+        assert!(is_valid_id("com.example.my-module_2"));
+        assert!(!is_valid_id("com.Example"));
+        assert!(!is_valid_id("com..example"));
+        assert!(!is_valid_id("-module"));
+
+        assert!(is_valid_language("en"));
+        assert!(is_valid_language("pt-BR"));
+        assert!(is_valid_language("zh-Hant-TW"));
+        assert!(!is_valid_language("EN"));
+        assert!(!is_valid_language("e"));
+        assert!(!is_valid_language("en-"));
+    }
+
+    #[test]
+    fn loads_manifests_with_missing_fields() {
+        let manifest = Manifest::parse(r#"{ "name": "Example", "features": ["download"] }"#);
+        let manifest = manifest.unwrap();
+        assert_eq!(manifest.name, "Example");
+        assert_eq!(manifest.features, ["download"]);
+        assert!(Manifest::load(&PathBuf::from("/nonexistent")).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::event_loop::{self, LoopError};
+use crate::pack;
+use crate::runtime::{self, Exception, InvokeError, Script};
+use crate::source_map::SourceMap;
+use crate::typescript;
+use clap::ValueEnum;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// How long top-level `await`s in a module may take before the load fails.
+const EVALUATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a module's script is compiled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// ES module if the script has `import` or `export` statements or ends
+    /// in `.mjs`, classic script otherwise
+    #[default]
+    Auto,
+    /// Classic script that defines a global `source` with a `default` class
+    Script,
+    /// ES module whose default export is the module class
+    Esm,
+}
+
+impl Format {
+    /// Settles `Auto` for a script.
+    pub fn resolve(self, script: &Script) -> Format {
+        static STATEMENT: OnceLock<Regex> = OnceLock::new();
+        if self != Format::Auto {
+            return self;
+        }
+        let statement = STATEMENT
+            .get_or_init(|| Regex::new(r#"(?m)^\s*(import\s*[\w{*"'.]|export\s)"#).unwrap());
+        if script.name.ends_with(".mjs") || statement.is_match(&script.source) {
+            Format::Esm
+        } else {
+            Format::Script
+        }
+    }
+}
+
+/// The files a module's script may import, by their `/` separated path
+/// relative to the module.
+pub struct Files {
+    /// The path of the script itself.
+    pub entry: String,
+    pub location: Location,
+}
+
+/// Where the files of a module are read from.
+pub enum Location {
+    Dir(PathBuf),
+    /// The entries of a packed module, named after the archive in errors.
+    Archive(PathBuf, Arc<BTreeMap<String, Vec<u8>>>),
+}
+
+impl Files {
+    /// The name a file is compiled under, so errors point at it.
+    pub fn name(&self, path: &str) -> String {
+        match &self.location {
+            Location::Dir(dir) => dir.join(path).display().to_string(),
+            Location::Archive(archive, _) => format!("{}/{}", archive.display(), path),
+        }
+    }
+
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        match &self.location {
+            Location::Dir(dir) => fs::read(dir.join(path)).ok(),
+            Location::Archive(_, entries) => entries.get(path).cloned(),
+        }
+    }
+
+    /// Whether the file at `path` has to be transpiled before it runs.
+    /// Packed modules hold JavaScript only, `chouten pack` transpiles their
+    /// TypeScript.
+    pub fn is_typescript(&self, path: &str) -> bool {
+        matches!(self.location, Location::Dir(_)) && typescript::is_typescript(path)
+    }
+
+    /// Finds the source map of the file at `path`, given its compiled
+    /// source. Maps are read relative to the file.
+    pub fn source_map(&self, path: &str, source: &str) -> Option<SourceMap> {
+        let name = self.name(path);
+        let path = Path::new(path);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        SourceMap::find(&name, &file_name, source, |relative| {
+            self.read(&pack::normalise(&dir.join(relative))?)
+        })
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        match &self.location {
+            Location::Dir(dir) => dir.join(path).is_file(),
+            Location::Archive(_, entries) => entries.contains_key(path),
+        }
+    }
+
+    /// Resolves an import relative to the file at `referrer`. Only relative
+    /// specifiers that stay inside the module are allowed, and the `.js` or
+    /// `.ts` extension or `/index.js` may be left out.
+    fn resolve(&self, referrer: &str, specifier: &str) -> Result<String, String> {
+        if !specifier.starts_with("./") && !specifier.starts_with("../") {
+            return Err(format!(
+                "Cannot import {:?}: only relative imports inside the module are supported.",
+                specifier
+            ));
+        }
+        let dir = Path::new(referrer).parent().unwrap_or(Path::new(""));
+        let path = pack::normalise(&dir.join(specifier))
+            .ok_or_else(|| format!("Cannot import {:?}: it is outside the module.", specifier))?;
+
+        [
+            path.clone(),
+            format!("{}.js", path),
+            format!("{}.mjs", path),
+            format!("{}.ts", path),
+            format!("{}/index.js", path),
+            format!("{}/index.ts", path),
+        ]
+        .into_iter()
+        .find(|candidate| self.exists(candidate))
+        .ok_or_else(|| {
+            format!(
+                "Cannot import {:?}: {} does not exist.",
+                specifier,
+                self.name(&path)
+            )
+        })
+    }
+}
+
+/// The modules compiled in an isolate, so imports can be resolved relative
+/// to the module importing them and every file is compiled once.
+struct ModuleMap {
+    files: Arc<Files>,
+    paths: HashMap<v8::Global<v8::Module>, String>,
+    modules: HashMap<String, v8::Global<v8::Module>>,
+}
+
+impl ModuleMap {
+    fn insert(&mut self, path: String, module: v8::Global<v8::Module>) {
+        self.paths.insert(module.clone(), path.clone());
+        self.modules.insert(path, module);
+    }
+}
+
+/// Compiles a file as an ES module and remembers it under `path`.
+fn compile<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    files: &Files,
+    path: &str,
+    source: &str,
+) -> Option<v8::Local<'s, v8::Module>> {
+    let code = v8::String::new(scope, source).unwrap();
+    let origin = runtime::origin(scope, &files.name(path), true);
+    let source = v8::script_compiler::Source::new(code, Some(&origin));
+    let module = v8::script_compiler::compile_module(scope, source)?;
+
+    let global = v8::Global::new(scope, module);
+    scope
+        .get_slot_mut::<ModuleMap>()
+        .unwrap()
+        .insert(path.to_string(), global);
+    Some(module)
+}
+
+/// Evaluates a script as an ES module and instantiates its default export,
+/// or uses it as is when it is already an object.
+pub fn load<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    script: &Script,
+) -> Result<v8::Local<'s, v8::Object>, InvokeError> {
+    let files = script.files.clone();
+    scope.set_slot(ModuleMap {
+        files: files.clone(),
+        paths: HashMap::new(),
+        modules: HashMap::new(),
+    });
+
+    let try_catch = &mut v8::TryCatch::new(scope);
+
+    let module = match compile(try_catch, &files, &files.entry, &script.source) {
+        Some(module) => module,
+        None => return Err(InvokeError::Compile(Exception::caught(try_catch))),
+    };
+
+    // Imported files are compiled while linking, so their syntax errors
+    // surface here.
+    if module.instantiate_module(try_catch, resolve) != Some(true) {
+        return Err(InvokeError::Compile(Exception::caught(try_catch)));
+    }
+
+    let evaluation = match module.evaluate(try_catch) {
+        Some(evaluation) => evaluation,
+        None => return Err(InvokeError::Load(Exception::caught(try_catch))),
+    };
+    // Modules evaluate to a promise, which stays pending while top-level
+    // `await`s are.
+    if let Ok(promise) = v8::Local::<v8::Promise>::try_from(evaluation) {
+        match event_loop::run_until_settled(try_catch, promise, EVALUATION_TIMEOUT) {
+            Ok(_) => {}
+            Err(LoopError::Rejected(error)) => {
+                return Err(InvokeError::Load(Exception::new(try_catch, error)))
+            }
+            Err(LoopError::TimedOut) => return Err(InvokeError::TimedOut(EVALUATION_TIMEOUT)),
+            Err(LoopError::Stalled) => return Err(InvokeError::Stalled),
+        }
+    }
+
+    let namespace = v8::Local::<v8::Object>::try_from(module.get_module_namespace()).unwrap();
+    let key = v8::String::new(try_catch, "default").unwrap();
+    let default = namespace
+        .get(try_catch, key.into())
+        .ok_or(InvokeError::NotAnObject)?;
+
+    let instance = match v8::Local::<v8::Function>::try_from(default) {
+        Ok(class) => match class.new_instance(try_catch, &[]) {
+            Some(instance) => instance,
+            None => return Err(InvokeError::Load(Exception::caught(try_catch))),
+        },
+        Err(_) => {
+            v8::Local::<v8::Object>::try_from(default).map_err(|_| InvokeError::NotAnObject)?
+        }
+    };
+    Ok(instance)
+}
+
+/// Resolves a static import against the module that contains it. Files are
+/// compiled the first time they are imported.
+fn resolve<'s>(
+    context: v8::Local<'s, v8::Context>,
+    specifier: v8::Local<'s, v8::String>,
+    _import_assertions: v8::Local<'s, v8::FixedArray>,
+    referrer: v8::Local<'s, v8::Module>,
+) -> Option<v8::Local<'s, v8::Module>> {
+    let scope = &mut unsafe { v8::CallbackScope::new(context) };
+    let specifier = specifier.to_rust_string_lossy(scope);
+
+    let referrer = v8::Global::new(scope, referrer);
+    let map = scope.get_slot::<ModuleMap>().unwrap();
+    let files = map.files.clone();
+    let referrer = map.paths.get(&referrer).cloned().unwrap_or_default();
+
+    let path = match files.resolve(&referrer, &specifier) {
+        Ok(path) => path,
+        Err(message) => return throw(scope, &message),
+    };
+
+    let map = scope.get_slot::<ModuleMap>().unwrap();
+    if let Some(module) = map.modules.get(&path).cloned() {
+        return Some(v8::Local::new(scope, module));
+    }
+
+    let source = match files.read(&path).map(String::from_utf8) {
+        Some(Ok(source)) if files.is_typescript(&path) => {
+            match typescript::transpile(&files.name(&path), &source) {
+                Ok(source) => source,
+                Err(message) => return throw(scope, &message),
+            }
+        }
+        Some(Ok(source)) => source,
+        Some(Err(_)) => return throw(scope, &format!("{} is not valid UTF-8.", files.name(&path))),
+        None => return throw(scope, &format!("Could not read {}.", files.name(&path))),
+    };
+    if let Some(source_map) = files.source_map(&path, &source) {
+        Arc::new(source_map).install(scope);
+    }
+    compile(scope, &files, &path, &source)
+}
+
+fn throw<'s, T>(scope: &mut v8::HandleScope<'s>, message: &str) -> Option<T> {
+    let message = v8::String::new(scope, message).unwrap();
+    let exception = v8::Exception::error(scope, message);
+    scope.throw_exception(exception);
+    None
+}
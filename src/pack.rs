@@ -0,0 +1,503 @@
+use crate::manifest::{self, Manifest, MANIFEST_FILE};
+use crate::typescript;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+/// File extension of packed modules.
+pub const ARCHIVE_EXTENSION: &str = "module";
+
+/// Name of the checksum file embedded in every archive.
+pub const CHECKSUM_FILE: &str = "checksum.json";
+
+/// Directories that only matter while developing a module.
+const DEV_DIRS: &[&str] = &["node_modules", "tests", "cassettes", "__snapshots__"];
+
+/// Files that only matter while developing a module.
+const DEV_FILES: &[&str] = &[
+    "package.json",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "yarn.lock",
+    "tsconfig.json",
+];
+
+/// Extensions of files that only matter while developing a module.
+const DEV_EXTENSIONS: &[&str] = &["map", ARCHIVE_EXTENSION];
+
+/// Suffix of TypeScript declaration files, which hold no code.
+const DECLARATION_SUFFIX: &str = ".d.ts";
+
+/// Packs the module in `dir` into an archive the app can install.
+///
+/// The module has to pass `chouten validate` first. Entries are stored in
+/// a fixed order with fixed timestamps, so packing the same sources twice
+/// gives the same archive. Returns the path of the archive and its digest.
+pub fn pack(dir: &Path, output: Option<&Path>) -> Result<(PathBuf, String), String> {
+    let (manifest, violations) = manifest::validate(dir)?;
+    if !violations.is_empty() {
+        let mut message = format!("{} is not a valid module:", dir.display());
+        for violation in &violations {
+            message.push_str(&format!("\n  {}", violation));
+        }
+        return Err(message);
+    }
+
+    let files = collect_files(dir, &manifest)?;
+    let mut contents = build(files)?;
+
+    let (checksum, digest) = checksum(&contents);
+    contents.insert(CHECKSUM_FILE.to_string(), checksum.into_bytes());
+
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => PathBuf::from(format!(
+            "{}-{}.{}",
+            manifest.id, manifest.version, ARCHIVE_EXTENSION
+        )),
+    };
+    write_archive(&output, &contents, &digest)
+        .map_err(|err| format!("Could not write {}: {}", output.display(), err))?;
+
+    Ok((output, digest))
+}
+
+/// Reads the files that go into the archive.
+///
+/// TypeScript files are transpiled and shipped as JavaScript, `.ts` as `.js`
+/// and `.mts` as `.mjs`. Imports that name a renamed file and the manifest's
+/// `script` are rewritten to match.
+fn build(files: BTreeMap<String, PathBuf>) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let mut renamed = BTreeMap::new();
+    for name in files.keys() {
+        if let Some(shipped) = shipped_name(name) {
+            if files.contains_key(&shipped) {
+                return Err(format!(
+                    "{} and {} would both be shipped as {}.",
+                    name, shipped, shipped
+                ));
+            }
+            renamed.insert(name.clone(), shipped);
+        }
+    }
+
+    let mut contents = BTreeMap::new();
+    for (name, path) in files {
+        let mut bytes =
+            fs::read(&path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        let mut shipped = name.clone();
+        if let Some(shipped_as) = renamed.get(&name) {
+            let source = String::from_utf8(bytes)
+                .map_err(|_| format!("{} is not valid UTF-8.", path.display()))?;
+            // Transpiled under the entry name, so the source map does not
+            // depend on where the module was packed.
+            let source = typescript::transpile(&name, &source)?;
+            bytes = rename_imports(shipped_as, &source, &renamed)?.into_bytes();
+            shipped = shipped_as.clone();
+        } else if name == MANIFEST_FILE {
+            bytes = rename_script(&path, bytes, &renamed)?;
+        } else if is_javascript(&name) {
+            // Classic scripts may not parse as modules, but cannot import
+            // anything either.
+            if let Ok(source) = std::str::from_utf8(&bytes) {
+                if let Ok(source) = rename_imports(&name, source, &renamed) {
+                    bytes = source.into_bytes();
+                }
+            }
+        }
+        contents.insert(shipped, bytes);
+    }
+    Ok(contents)
+}
+
+/// The name a TypeScript file is shipped under.
+fn shipped_name(name: &str) -> Option<String> {
+    if !typescript::is_typescript(name) {
+        return None;
+    }
+    if let Some(stem) = name.strip_suffix(".mts") {
+        return Some(format!("{}.mjs", stem));
+    }
+    name.strip_suffix(".ts").map(|stem| format!("{}.js", stem))
+}
+
+fn is_javascript(name: &str) -> bool {
+    name.ends_with(".js") || name.ends_with(".mjs")
+}
+
+/// Points relative imports of the JavaScript file `name` that name a
+/// renamed file at its new name.
+fn rename_imports(
+    name: &str,
+    source: &str,
+    renamed: &BTreeMap<String, String>,
+) -> Result<String, String> {
+    if renamed.is_empty() {
+        return Ok(source.to_string());
+    }
+    let dir = Path::new(name).parent().unwrap_or(Path::new(""));
+
+    let mut output = source.to_string();
+    // From the end, so earlier ranges stay valid.
+    for (specifier, range) in typescript::import_specifiers(name, source)?
+        .into_iter()
+        .rev()
+    {
+        if !specifier.starts_with("./") && !specifier.starts_with("../") {
+            continue;
+        }
+        let shipped = match normalise(&dir.join(&specifier)) {
+            Some(target) => match renamed.get(&target) {
+                Some(shipped) => shipped,
+                None => continue,
+            },
+            None => continue,
+        };
+        let extension = shipped.rsplit('.').next().unwrap();
+        let stem = &specifier[..specifier.rfind('.').unwrap()];
+        let quote = &source[range.start..range.start + 1];
+        output.replace_range(range, &format!("{}{}.{}{}", quote, stem, extension, quote));
+    }
+    Ok(output)
+}
+
+/// Points the manifest's `script` at the name the script is shipped under.
+fn rename_script(
+    path: &Path,
+    bytes: Vec<u8>,
+    renamed: &BTreeMap<String, String>,
+) -> Result<Vec<u8>, String> {
+    let mut manifest: serde_json::Value =
+        serde_json::from_slice(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let script = manifest["script"]
+        .as_str()
+        .map(Path::new)
+        .and_then(normalise);
+    match script.and_then(|script| renamed.get(&script)) {
+        Some(shipped) => {
+            manifest["script"] = shipped.clone().into();
+            Ok((serde_json::to_string_pretty(&manifest).unwrap() + "\n").into_bytes())
+        }
+        None => Ok(bytes),
+    }
+}
+
+/// Lists the files that go into the archive, keyed by their normalised name.
+fn collect_files(dir: &Path, manifest: &Manifest) -> Result<BTreeMap<String, PathBuf>, String> {
+    let mut files = BTreeMap::new();
+    walk(dir, dir, &mut files)?;
+
+    // The files the manifest points to are shipped even if they look like
+    // development files.
+    for required in [MANIFEST_FILE, &manifest.script, &manifest.icon] {
+        let name = normalise(Path::new(required))
+            .ok_or_else(|| format!("{} points outside of the module directory.", required))?;
+        files.insert(name, dir.join(required));
+    }
+
+    Ok(files)
+}
+
+fn walk(root: &Path, dir: &Path, files: &mut BTreeMap<String, PathBuf>) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        let file_type = entry
+            .file_type()
+            .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        if file_type.is_dir() {
+            if !DEV_DIRS.contains(&file_name.as_str()) {
+                walk(root, &path, files)?;
+            }
+            continue;
+        }
+
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if DEV_FILES.contains(&file_name.as_str())
+            || DEV_EXTENSIONS.contains(&extension)
+            || file_name.ends_with(DECLARATION_SUFFIX)
+        {
+            continue;
+        }
+
+        let relative = path.strip_prefix(root).unwrap();
+        if let Some(name) = normalise(relative) {
+            files.insert(name, path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns a relative path into an archive entry name: `/` separated, without
+/// `.` segments and never escaping the module directory.
+pub fn normalise(path: &Path) -> Option<String> {
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Builds the checksum file and the digest that identifies the archive.
+///
+/// The digest is the SHA-256 of one `<sha256>  <name>` line per file, in
+/// entry order, so it can be recomputed from the archive alone.
+pub fn checksum(contents: &BTreeMap<String, Vec<u8>>) -> (String, String) {
+    let mut files = serde_json::Map::new();
+    let mut digest = Sha256::new();
+
+    for (name, bytes) in contents {
+        if name == CHECKSUM_FILE {
+            continue;
+        }
+        let hash = format!("{:x}", Sha256::digest(bytes));
+        digest.update(format!("{}  {}\n", hash, name));
+        files.insert(name.clone(), hash.into());
+    }
+
+    let digest = format!("{:x}", digest.finalize());
+    let checksum = json!({
+        "algorithm": "sha256",
+        "digest": digest,
+        "files": files,
+    });
+    (
+        serde_json::to_string_pretty(&checksum).unwrap() + "\n",
+        digest,
+    )
+}
+
+fn write_archive(
+    output: &Path,
+    contents: &BTreeMap<String, Vec<u8>>,
+    digest: &str,
+) -> zip::result::ZipResult<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default())
+        .unix_permissions(0o644);
+
+    for (name, bytes) in contents {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(bytes)?;
+    }
+
+    zip.set_comment(format!("sha256:{}", digest));
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalises_entry_names() {
+        let name = |path: &str| normalise(Path::new(path));
+        assert_eq!(name("code.js").as_deref(), Some("code.js"));
+        assert_eq!(name("./lib/./util.js").as_deref(), Some("lib/util.js"));
+        assert_eq!(name("lib/../code.js").as_deref(), Some("code.js"));
+        assert_eq!(name("../code.js"), None);
+        assert_eq!(name("lib/../../code.js"), None);
+        assert_eq!(name("/etc/passwd"), None);
+        assert_eq!(name("."), None);
+        assert_eq!(name(""), None);
+    }
+
+    #[test]
+    fn checksums_every_file_but_the_checksum() {
+        let mut contents = BTreeMap::new();
+        contents.insert("code.js".to_string(), b"export default 1;".to_vec());
+        contents.insert("metadata.json".to_string(), b"{}".to_vec());
+
+        let (checksum, digest) = checksum(&contents);
+        let code_hash = format!("{:x}", Sha256::digest(b"export default 1;"));
+        let manifest_hash = format!("{:x}", Sha256::digest(b"{}"));
+        let lines = format!("{}  code.js\n{}  metadata.json\n", code_hash, manifest_hash);
+        assert_eq!(digest, format!("{:x}", Sha256::digest(lines)));
+
+        let checksum: serde_json::Value = serde_json::from_str(&checksum).unwrap();
+        assert_eq!(
+            checksum,
+            json!({
+                "algorithm": "sha256",
+                "digest": digest,
+                "files": { "code.js": code_hash, "metadata.json": manifest_hash },
+            })
+        );
+
+        // Recomputing from an archive that already has the file agrees.
+        contents.insert(CHECKSUM_FILE.to_string(), b"stale".to_vec());
+        assert_eq!(super::checksum(&contents).1, digest);
+    }
+
+    #[test]
+    fn leaves_development_files_out() {
+        let dir = std::env::temp_dir().join(format!("chouten-pack-{}", std::process::id()));
+        for file in [
+            "metadata.json",
+            "code.js",
+            "icon.png",
+            "lib/util.js",
+            "lib/helpers.ts",
+            "lib/types.d.ts",
+            "code.js.map",
+            "package.json",
+            ".env",
+            "tests/module.toml",
+            "node_modules/dep/index.js",
+            "cassettes/search/0.json",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let manifest = Manifest {
+            script: "./code.js".to_string(),
+            icon: "icon.png".to_string(),
+            ..Manifest::default()
+        };
+        let files = collect_files(&dir, &manifest);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = files.unwrap().into_keys().collect();
+        assert_eq!(
+            names,
+            [
+                "code.js",
+                "icon.png",
+                "lib/helpers.ts",
+                "lib/util.js",
+                "metadata.json"
+            ]
+        );
+    }
+
+    fn write_module(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chouten-pack-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn ships_typescript_as_javascript() {
+        let dir = write_module(
+            "typescript",
+            &[
+                (
+                    "metadata.json",
+                    r#"{ "script": "./code.ts", "icon": "icon.png" }"#,
+                ),
+                ("icon.png", ""),
+                (
+                    "code.ts",
+                    "import { util } from \"./lib/util.ts\";\n\
+                     import { extra } from './lib/extra';\n\
+                     export * from \"./lib/esm.mts\";\n\
+                     export default class { n: number = util + extra; }\n",
+                ),
+                ("lib/util.ts", "export const util: number = 1;\n"),
+                ("lib/extra.ts", "export const extra: number = 2;\n"),
+                ("lib/esm.mts", "export const esm: string = \"esm\";\n"),
+                ("lib/plain.js", "export { util } from \"./util.ts\";\n"),
+                (
+                    "lib/classic.js",
+                    "with (Math) { var ts = \"./util.ts\"; }\n",
+                ),
+            ],
+        );
+        let files = collect_files(&dir, &Manifest::load(&dir).unwrap()).unwrap();
+        let contents = build(files);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let contents = contents.unwrap();
+        let names: Vec<_> = contents.keys().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            [
+                "code.js",
+                "icon.png",
+                "lib/classic.js",
+                "lib/esm.mjs",
+                "lib/extra.js",
+                "lib/plain.js",
+                "lib/util.js",
+                "metadata.json"
+            ]
+        );
+
+        let text = |name: &str| String::from_utf8(contents[name].clone()).unwrap();
+        let code = text("code.js");
+        assert!(code.contains("from \"./lib/util.js\""), "{}", code);
+        // Extensionless imports resolve to the `.js` file as they are.
+        assert!(code.contains("from './lib/extra'"), "{}", code);
+        assert!(code.contains("from \"./lib/esm.mjs\""), "{}", code);
+        assert!(!code.contains("number"), "{}", code);
+        assert_eq!(
+            text("lib/plain.js"),
+            "export { util } from \"./util.js\";\n"
+        );
+        // Scripts that are not modules are shipped as they are.
+        assert_eq!(
+            text("lib/classic.js"),
+            "with (Math) { var ts = \"./util.ts\"; }\n"
+        );
+
+        let manifest: serde_json::Value = serde_json::from_str(&text("metadata.json")).unwrap();
+        assert_eq!(manifest["script"], "code.js");
+        assert_eq!(manifest["icon"], "icon.png");
+    }
+
+    #[test]
+    fn refuses_to_ship_two_files_under_one_name() {
+        let dir = write_module(
+            "collision",
+            &[("code.ts", "export default 1;\n"), ("code.js", "")],
+        );
+        let mut files = BTreeMap::new();
+        walk(&dir, &dir, &mut files).unwrap();
+        let result = build(files);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            result.unwrap_err(),
+            "code.ts and code.js would both be shipped as code.js."
+        );
+    }
+}
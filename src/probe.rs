@@ -0,0 +1,919 @@
+use crate::cassette::{Cassette, Mode};
+use crate::http::{Request, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, RANGE};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// How long a single probe request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The outcome of one check, e.g. that a playlist parses or a segment loads.
+pub struct Finding {
+    pub subject: String,
+    pub result: Result<String, String>,
+}
+
+/// Everything that was checked for one stream or subtitle track.
+pub struct Report {
+    pub title: String,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn new(title: String) -> Report {
+        Report {
+            title,
+            findings: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, subject: impl Into<String>, result: Result<String, String>) {
+        self.findings.push(Finding {
+            subject: subject.into(),
+            result,
+        });
+    }
+
+    pub fn failures(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.result.is_err())
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        for finding in &self.findings {
+            match &finding.result {
+                Ok(message) => write!(f, "\n  ok    {}: {}", finding.subject, message)?,
+                Err(message) => write!(f, "\n  FAIL  {}: {}", finding.subject, message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the streams and subtitles a `sources()` result points to
+/// can actually be loaded, sending the headers the result asks for.
+///
+/// HLS playlists are followed from the master playlist to every variant,
+/// and the init segments, keys and first and last segment of each variant
+/// are requested, limited to their byte range if they have one. Subtitle
+/// files are downloaded and parsed as WebVTT, SRT or ASS.
+///
+/// Requests go through `cassette` like the module's own, so a replayed run
+/// probes without the network.
+pub fn probe(sources: &Value, cassette: Option<&Arc<Cassette>>) -> Result<Vec<Report>, String> {
+    let headers = read_headers(&sources["headers"])?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| format!("Could not start the probe runtime: {}", err))?;
+
+    runtime.block_on(async {
+        // Pooled connections belong to the runtime that opened them, so the
+        // probe does not share the client of the module's event loop.
+        let prober = Prober {
+            client: reqwest::Client::new(),
+            headers,
+            cassette: cassette.cloned(),
+        };
+        let mut reports = Vec::new();
+        for stream in sources["streams"].as_array().into_iter().flatten() {
+            reports.push(prober.probe_stream(stream).await);
+        }
+        for subtitle in sources["subtitles"].as_array().into_iter().flatten() {
+            reports.push(prober.probe_subtitle(subtitle).await);
+        }
+        Ok(reports)
+    })
+}
+
+fn read_headers(value: &Value) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in value.as_object().into_iter().flatten() {
+        let value = value.as_str().unwrap_or_default();
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name in sources: {}", name))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for header {} in sources: {}", name, value))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Sends the probe requests with the headers the sources asked for.
+struct Prober {
+    client: reqwest::Client,
+    headers: HeaderMap,
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl Prober {
+    async fn probe_stream(&self, stream: &Value) -> Report {
+        let file = stream["file"].as_str().unwrap_or_default();
+        let kind = stream["type"].as_str().unwrap_or_default();
+        let mut report = Report::new(format!(
+            "stream {} ({}) {}",
+            stream["quality"].as_str().unwrap_or("?"),
+            kind,
+            file
+        ));
+
+        let url = match Url::parse(file) {
+            Ok(url) => url,
+            Err(err) => {
+                report.push("file", Err(format!("not an absolute url: {}", err)));
+                return report;
+            }
+        };
+
+        match kind {
+            "hls" => self.probe_hls(&url, &mut report).await,
+            "dash" => {
+                let result = self.fetch_text(&url).await.and_then(|text| {
+                    if !text.contains("<MPD") {
+                        return Err("not a DASH manifest, no <MPD> element".to_string());
+                    }
+                    Ok(format!(
+                        "DASH manifest with {} representations",
+                        text.matches("<Representation").count()
+                    ))
+                });
+                report.push("manifest", result);
+            }
+            _ => report.push("file", self.check_reachable(&url).await),
+        }
+        report
+    }
+
+    async fn probe_hls(&self, url: &Url, report: &mut Report) {
+        let playlist = match self
+            .fetch_text(url)
+            .await
+            .and_then(|text| Playlist::parse(&text, url))
+        {
+            Ok(playlist) => playlist,
+            Err(err) => {
+                report.push("playlist", Err(err));
+                return;
+            }
+        };
+
+        if playlist.variants.is_empty() {
+            self.check_media_playlist("playlist", url, playlist, report)
+                .await;
+            return;
+        }
+
+        report.push(
+            "master playlist",
+            Ok(format!("{} variants", playlist.variants.len())),
+        );
+        for rendition in &playlist.renditions {
+            report.push(
+                format!("rendition {}", rendition),
+                self.check_reachable(rendition).await,
+            );
+        }
+        for variant in &playlist.variants {
+            let subject = match &variant.resolution {
+                Some(resolution) => format!("variant {} {}", resolution, variant.url),
+                None => format!("variant {}", variant.url),
+            };
+            match self
+                .fetch_text(&variant.url)
+                .await
+                .and_then(|text| Playlist::parse(&text, &variant.url))
+            {
+                Ok(media) => {
+                    self.check_media_playlist(&subject, &variant.url, media, report)
+                        .await
+                }
+                Err(err) => report.push(subject, Err(err)),
+            }
+        }
+    }
+
+    async fn check_media_playlist(
+        &self,
+        subject: &str,
+        url: &Url,
+        playlist: Playlist,
+        report: &mut Report,
+    ) {
+        if playlist.segments.is_empty() {
+            report.push(subject, Err(format!("{} has no segments", url)));
+            return;
+        }
+        report.push(
+            subject,
+            Ok(format!(
+                "{} segments, {:.0}s",
+                playlist.segments.len(),
+                playlist.duration
+            )),
+        );
+
+        for key in &playlist.keys {
+            report.push(format!("key {}", key), self.check_reachable(key).await);
+        }
+        for map in &playlist.maps {
+            report.push(
+                format!("init segment {}", map),
+                self.check_segment(map).await,
+            );
+        }
+
+        let first = &playlist.segments[0];
+        report.push(
+            format!("segment {}", first),
+            self.check_segment(first).await,
+        );
+        let last = &playlist.segments[playlist.segments.len() - 1];
+        if last != first {
+            report.push(format!("segment {}", last), self.check_segment(last).await);
+        }
+    }
+
+    async fn probe_subtitle(&self, subtitle: &Value) -> Report {
+        let file = subtitle["url"].as_str().unwrap_or_default();
+        let mut report = Report::new(format!(
+            "subtitle {} {}",
+            subtitle["language"].as_str().unwrap_or("?"),
+            file
+        ));
+
+        let result = match Url::parse(file) {
+            Ok(url) => self
+                .fetch_text(&url)
+                .await
+                .and_then(|text| check_subtitle(&text, url.path())),
+            Err(err) => Err(format!("not an absolute url: {}", err)),
+        };
+        report.push("file", result);
+        report
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        url: &Url,
+        range: Option<ByteRange>,
+    ) -> Result<(StatusCode, Response), String> {
+        let mut headers = self.headers.clone();
+        if let Some(range) = range {
+            let value = format!("bytes={}-{}", range.offset, range.end());
+            headers.insert(RANGE, HeaderValue::from_str(&value).unwrap());
+        }
+        let request = Request {
+            url: url.to_string(),
+            method,
+            headers,
+            body: None,
+        };
+
+        let entry = self
+            .cassette
+            .as_ref()
+            .map(|cassette| cassette.start(&request));
+        let response = match &entry {
+            Some(entry) if entry.mode() == Mode::Replay => entry.replay(&request)?,
+            entry => {
+                let response = self
+                    .client
+                    .request(request.method.clone(), url.clone())
+                    .headers(request.headers.clone())
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await
+                    .map_err(|err| err.to_string())?;
+                let response = Response::read(response).await;
+                if let Some(entry) = entry {
+                    entry.record(&request, &response)?;
+                }
+                response
+            }
+        };
+
+        let status = u16::try_from(response.status_code)
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .ok_or_else(|| format!("invalid status {}", response.status_code))?;
+        Ok((status, response))
+    }
+
+    /// Checks that a url answers with a success status. Many CDNs refuse HEAD,
+    /// so a rejected HEAD is retried as a GET of the first byte.
+    async fn check_reachable(&self, url: &Url) -> Result<String, String> {
+        let (mut status, mut response) = self.send(reqwest::Method::HEAD, url, None).await?;
+        if !status.is_success() {
+            let first_byte = ByteRange {
+                offset: 0,
+                length: 1,
+            };
+            (status, response) = self
+                .send(reqwest::Method::GET, url, Some(first_byte))
+                .await?;
+        }
+
+        if !status.is_success() {
+            return Err(describe_status(status));
+        }
+        let content_type = match response.content_type.as_str() {
+            "" => "no content type",
+            content_type => content_type,
+        };
+        Ok(format!("{}, {}", describe_status(status), content_type))
+    }
+
+    /// Checks a segment, requesting just its byte range if it has one. A
+    /// server that ignores the range and sends the whole file passes too.
+    async fn check_segment(&self, segment: &Segment) -> Result<String, String> {
+        let range = match segment.range {
+            Some(range) => range,
+            None => return self.check_reachable(&segment.url).await,
+        };
+        let (status, _) = self
+            .send(reqwest::Method::GET, &segment.url, Some(range))
+            .await?;
+        if !status.is_success() {
+            return Err(describe_status(status));
+        }
+        Ok(describe_status(status))
+    }
+
+    async fn fetch_text(&self, url: &Url) -> Result<String, String> {
+        let (status, response) = self.send(reqwest::Method::GET, url, None).await?;
+        if !status.is_success() {
+            return Err(format!("{} from {}", describe_status(status), url));
+        }
+        Ok(response.text())
+    }
+}
+
+fn describe_status(status: StatusCode) -> String {
+    format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+    .trim_end()
+    .to_string()
+}
+
+/// The parts of an HLS playlist that point to other resources.
+struct Playlist {
+    /// Variant streams of a master playlist.
+    variants: Vec<Variant>,
+    /// Alternative audio and subtitle renditions of a master playlist.
+    renditions: Vec<Url>,
+    /// Segments of a media playlist.
+    segments: Vec<Segment>,
+    /// Init segments of a media playlist, from `#EXT-X-MAP`.
+    maps: Vec<Segment>,
+    /// Encryption keys of a media playlist.
+    keys: Vec<Url>,
+    /// Total duration of the segments in seconds.
+    duration: f64,
+}
+
+struct Variant {
+    url: Url,
+    resolution: Option<String>,
+}
+
+/// A media segment, or the part of a file it takes up when the playlist
+/// gives it a byte range.
+#[derive(Debug, PartialEq)]
+struct Segment {
+    url: Url,
+    range: Option<ByteRange>,
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url)?;
+        if let Some(range) = self.range {
+            write!(f, " bytes {}-{}", range.offset, range.end())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ByteRange {
+    offset: u64,
+    length: u64,
+}
+
+impl ByteRange {
+    /// Parses `<length>[@<offset>]`. Without an offset the range starts where
+    /// `previous`, the last range of the same file, ended.
+    fn parse(value: &str, previous: Option<ByteRange>) -> Option<ByteRange> {
+        let (length, offset) = match value.split_once('@') {
+            Some((length, offset)) => (length, Some(offset.trim().parse().ok()?)),
+            None => (value, None),
+        };
+        let length = length.trim().parse().ok().filter(|&length| length > 0)?;
+        let offset = offset.or_else(|| previous.map(|previous| previous.end() + 1))?;
+        Some(ByteRange { offset, length })
+    }
+
+    /// The last byte of the range.
+    fn end(self) -> u64 {
+        self.offset + self.length - 1
+    }
+}
+
+impl Playlist {
+    fn parse(text: &str, base: &Url) -> Result<Playlist, String> {
+        let mut lines = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty());
+        if lines.next().map(|line| line.trim_start_matches('\u{feff}')) != Some("#EXTM3U") {
+            return Err(format!(
+                "{} is not an HLS playlist, #EXTM3U is missing",
+                base
+            ));
+        }
+
+        let resolve = |uri: &str| {
+            base.join(uri)
+                .map_err(|err| format!("invalid uri {:?} in {}: {}", uri, base, err))
+        };
+
+        let mut playlist = Playlist {
+            variants: Vec::new(),
+            renditions: Vec::new(),
+            segments: Vec::new(),
+            maps: Vec::new(),
+            keys: Vec::new(),
+            duration: 0.0,
+        };
+        // Which kind of uri the next plain line is, with the resolution of
+        // a variant.
+        let mut pending: Option<Option<String>> = None;
+        let mut in_segment = false;
+        // The byte range of the next segment, and the last range seen, which
+        // a range without an offset continues from.
+        let mut range: Option<String> = None;
+        let mut previous: Option<(Url, ByteRange)> = None;
+        let mut parse_range = |url: &Url, value: &str| {
+            let continued = previous
+                .as_ref()
+                .filter(|(previous, _)| previous == url)
+                .map(|&(_, range)| range);
+            let range = ByteRange::parse(value, continued)
+                .ok_or_else(|| format!("invalid byte range {:?} in {}", value, base))?;
+            previous = Some((url.clone(), range));
+            Ok::<_, String>(range)
+        };
+
+        for line in lines {
+            if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+                pending = Some(attribute(attributes, "RESOLUTION"));
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-MEDIA:") {
+                if let Some(uri) = attribute(attributes, "URI") {
+                    playlist.renditions.push(resolve(&uri)?);
+                }
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-KEY:") {
+                if let Some(uri) = attribute(attributes, "URI") {
+                    if !uri.starts_with("data:") && !uri.starts_with("skd:") {
+                        playlist.keys.push(resolve(&uri)?);
+                    }
+                }
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+                let uri = attribute(attributes, "URI")
+                    .ok_or_else(|| format!("#EXT-X-MAP without a URI in {}", base))?;
+                let url = resolve(&uri)?;
+                let range = match attribute(attributes, "BYTERANGE") {
+                    Some(value) => Some(parse_range(&url, &value)?),
+                    None => None,
+                };
+                playlist.maps.push(Segment { url, range });
+            } else if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+                range = Some(value.to_string());
+            } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+                let duration = info.split(',').next().unwrap_or_default();
+                playlist.duration += duration.trim().parse::<f64>().unwrap_or(0.0);
+                in_segment = true;
+            } else if line.starts_with('#') {
+                continue;
+            } else if let Some(resolution) = pending.take() {
+                playlist.variants.push(Variant {
+                    url: resolve(line)?,
+                    resolution,
+                });
+            } else if in_segment {
+                let url = resolve(line)?;
+                let range = match range.take() {
+                    Some(value) => Some(parse_range(&url, &value)?),
+                    None => None,
+                };
+                playlist.segments.push(Segment { url, range });
+                in_segment = false;
+            }
+        }
+
+        Ok(playlist)
+    }
+}
+
+/// Reads one attribute of an HLS tag, e.g. `URI` of
+/// `TYPE=AUDIO,URI="audio.m3u8"`, respecting quoted commas.
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                let next = quoted[end + 1..].trim_start_matches(',');
+                (&quoted[..end], next)
+            }
+            None => match value.split_once(',') {
+                Some((value, next)) => (value, next),
+                None => (value, ""),
+            },
+        };
+        if key.trim() == name {
+            return Some(value.to_string());
+        }
+        rest = next;
+    }
+    None
+}
+
+/// Parses a subtitle file and describes it. The format is taken from the
+/// content, falling back to the extension in `path`.
+fn check_subtitle(text: &str, path: &str) -> Result<String, String> {
+    let text = text.trim_start_matches('\u{feff}');
+    let extension = path
+        .rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    if text.starts_with("WEBVTT") || extension == "vtt" {
+        check_vtt(text)
+    } else if text.contains("[Script Info]") || extension == "ass" || extension == "ssa" {
+        check_ass(text)
+    } else {
+        check_srt(text)
+    }
+}
+
+fn check_vtt(text: &str) -> Result<String, String> {
+    if !text.starts_with("WEBVTT") {
+        return Err("not a WebVTT file, the WEBVTT header is missing".to_string());
+    }
+    let cues = count_cues(text, '.')?;
+    Ok(format!("WebVTT, {} cues", cues))
+}
+
+fn check_srt(text: &str) -> Result<String, String> {
+    let cues = count_cues(text, ',')?;
+    Ok(format!("SRT, {} cues", cues))
+}
+
+/// Counts the `start --> end` lines, checking the timestamps use the given
+/// fraction separator.
+fn count_cues(text: &str, separator: char) -> Result<usize, String> {
+    let mut cues = 0;
+    for (number, line) in text.lines().enumerate() {
+        let Some((start, end)) = line.split_once("-->") else {
+            continue;
+        };
+        // Cue settings may follow the end timestamp in WebVTT.
+        let end = end.split_whitespace().next().unwrap_or_default();
+        for timestamp in [start.trim(), end] {
+            if !is_timestamp(timestamp, separator) {
+                return Err(format!(
+                    "line {}: invalid timestamp {:?}",
+                    number + 1,
+                    timestamp
+                ));
+            }
+        }
+        cues += 1;
+    }
+    if cues == 0 {
+        return Err("no cues".to_string());
+    }
+    Ok(cues)
+}
+
+/// Matches `hh:mm:ss<separator>ttt` or `mm:ss<separator>ttt`.
+fn is_timestamp(timestamp: &str, separator: char) -> bool {
+    let Some((time, fraction)) = timestamp.rsplit_once(separator) else {
+        return false;
+    };
+    let parts: Vec<&str> = time.split(':').collect();
+    (2..=3).contains(&parts.len())
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        && fraction.len() == 3
+        && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+fn check_ass(text: &str) -> Result<String, String> {
+    if !text.contains("[Script Info]") {
+        return Err("not an ASS file, [Script Info] is missing".to_string());
+    }
+    if !text.contains("[Events]") {
+        return Err("no [Events] section".to_string());
+    }
+    let dialogues = text
+        .lines()
+        .filter(|line| line.trim_start().starts_with("Dialogue:"))
+        .count();
+    if dialogues == 0 {
+        return Err("no Dialogue lines".to_string());
+    }
+    Ok(format!("ASS, {} dialogue lines", dialogues))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example.com/show/master.m3u8").unwrap()
+    }
+
+    fn urls(segments: &[Segment]) -> Vec<&str> {
+        segments
+            .iter()
+            .map(|segment| segment.url.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_master_playlists() {
+        let text = "\u{feff}#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English, main\",URI=\"audio/en.m3u8\"
+#EXT-X-MEDIA:TYPE=CLOSED-CAPTIONS,GROUP-ID=\"cc\",NAME=\"CC1\"
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\"
+360p/index.m3u8
+
+#EXT-X-STREAM-INF:BANDWIDTH=2800000
+https://other.example.com/720p.m3u8
+";
+        let playlist = Playlist::parse(text, &base()).unwrap();
+
+        let variants: Vec<_> = playlist
+            .variants
+            .iter()
+            .map(|variant| (variant.url.as_str(), variant.resolution.as_deref()))
+            .collect();
+        assert_eq!(
+            variants,
+            [
+                (
+                    "https://cdn.example.com/show/360p/index.m3u8",
+                    Some("640x360")
+                ),
+                ("https://other.example.com/720p.m3u8", None),
+            ]
+        );
+        let renditions: Vec<_> = playlist.renditions.iter().map(Url::as_str).collect();
+        assert_eq!(renditions, ["https://cdn.example.com/show/audio/en.m3u8"]);
+        assert!(playlist.segments.is_empty());
+    }
+
+    #[test]
+    fn parses_media_playlists() {
+        let text = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-KEY:METHOD=AES-128,URI=\"/keys/1\"
+#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"skd://fairplay\"
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:9.5,
+seg0.ts
+#EXTINF:10.0,title
+seg1.ts
+#EXT-X-ENDLIST
+";
+        let playlist = Playlist::parse(text, &base()).unwrap();
+        assert!(playlist.variants.is_empty());
+        assert_eq!(
+            urls(&playlist.segments),
+            [
+                "https://cdn.example.com/show/seg0.ts",
+                "https://cdn.example.com/show/seg1.ts",
+            ]
+        );
+        assert_eq!(
+            urls(&playlist.maps),
+            ["https://cdn.example.com/show/init.mp4"]
+        );
+        let keys: Vec<_> = playlist.keys.iter().map(Url::as_str).collect();
+        assert_eq!(keys, ["https://cdn.example.com/keys/1"]);
+        assert_eq!(playlist.duration, 19.5);
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        let text = "#EXTM3U
+#EXT-X-MAP:URI=\"main.mp4\",BYTERANGE=\"720@0\"
+#EXTINF:4,
+#EXT-X-BYTERANGE:1000@720
+main.mp4
+#EXTINF:4,
+#EXT-X-BYTERANGE:500
+main.mp4
+#EXTINF:4,
+other.mp4
+";
+        let playlist = Playlist::parse(text, &base()).unwrap();
+        let range = |offset, length| Some(ByteRange { offset, length });
+        assert_eq!(playlist.maps[0].range, range(0, 720));
+        let ranges: Vec<_> = playlist
+            .segments
+            .iter()
+            .map(|segment| segment.range)
+            .collect();
+        assert_eq!(ranges, [range(720, 1000), range(1720, 500), None]);
+        assert_eq!(
+            playlist.segments[1].to_string(),
+            "https://cdn.example.com/show/main.mp4 bytes 1720-2219"
+        );
+
+        // Without an offset the range has to continue one of the same file.
+        let text = "#EXTM3U\n#EXTINF:4,\n#EXT-X-BYTERANGE:500\nmain.mp4\n";
+        assert!(Playlist::parse(text, &base()).is_err());
+        let text = "#EXTM3U\n#EXTINF:4,\n#EXT-X-BYTERANGE:0@0\nmain.mp4\n";
+        assert!(Playlist::parse(text, &base()).is_err());
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_playlists() {
+        assert!(Playlist::parse("<html></html>", &base()).is_err());
+        assert!(Playlist::parse("", &base()).is_err());
+        assert!(Playlist::parse("#EXTM3U\n#EXT-X-MAP:BYTERANGE=\"1@0\"\n", &base()).is_err());
+    }
+
+    #[test]
+    fn reads_attributes() {
+        let attributes = "TYPE=AUDIO,NAME=\"English, main\",DEFAULT=YES,URI=\"a.m3u8\"";
+        assert_eq!(attribute(attributes, "TYPE").as_deref(), Some("AUDIO"));
+        assert_eq!(
+            attribute(attributes, "NAME").as_deref(),
+            Some("English, main")
+        );
+        assert_eq!(attribute(attributes, "DEFAULT").as_deref(), Some("YES"));
+        assert_eq!(attribute(attributes, "URI").as_deref(), Some("a.m3u8"));
+        assert_eq!(attribute(attributes, "LANGUAGE"), None);
+        assert_eq!(attribute("NAME=\"unclosed", "NAME"), None);
+        assert_eq!(attribute("", "URI"), None);
+    }
+
+    #[test]
+    fn checks_timestamps() {
+        assert!(is_timestamp("00:01:02.345", '.'));
+        assert!(is_timestamp("01:02.345", '.'));
+        assert!(is_timestamp("100:00:00,000", ','));
+        assert!(!is_timestamp("00:01:02,345", '.'));
+        assert!(!is_timestamp("00:01:02.34", '.'));
+        assert!(!is_timestamp("02.345", '.'));
+        assert!(!is_timestamp("00::02.345", '.'));
+        assert!(!is_timestamp("0a:01:02.345", '.'));
+    }
+
+    #[test]
+    fn checks_subtitles() {
+        let vtt = "\u{feff}WEBVTT\n\n00:00.000 --> 00:02.000 align:start\nHello\n\n00:02.000 --> 00:04.000\nWorld\n";
+        assert_eq!(
+            check_subtitle(vtt, "/en.vtt"),
+            Ok("WebVTT, 2 cues".to_string())
+        );
+
+        let srt = "1\n00:00:00,000 --> 00:00:02,000\nHello\n";
+        assert_eq!(
+            check_subtitle(srt, "/en.srt"),
+            Ok("SRT, 1 cues".to_string())
+        );
+
+        let ass = "[Script Info]\nTitle: x\n\n[Events]\nFormat: Layer, Start, End, Text\nDialogue: 0,0:00:00.00,0:00:02.00,Hello\n";
+        assert_eq!(
+            check_subtitle(ass, "/subs"),
+            Ok("ASS, 1 dialogue lines".to_string())
+        );
+
+        // The extension decides when the content does not.
+        assert!(check_subtitle("1\n00:00:00,000 --> 00:00:02,000\n", "/en.vtt").is_err());
+        assert_eq!(
+            check_subtitle("WEBVTT\n\n00:00.000 --> 00:02,000\n", "/en.vtt"),
+            Err("line 3: invalid timestamp \"00:02,000\"".to_string())
+        );
+        assert_eq!(check_subtitle("", "/en.srt"), Err("no cues".to_string()));
+        assert_eq!(
+            check_subtitle("[Script Info]\n", "/en.ass"),
+            Err("no [Events] section".to_string())
+        );
+    }
+
+    /// Serves fixed responses by path on a local port, one request per
+    /// connection, and returns the base url.
+    fn serve(routes: Vec<(&'static str, &'static str)>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default();
+                let path = parts.next().unwrap_or_default();
+                let response = match routes.iter().find(|(route, _)| *route == path) {
+                    Some((_, body)) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        if method == "HEAD" { "" } else { body }
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string(),
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn probes_streams_and_subtitles() {
+        let base = serve(vec![
+            (
+                "/master.m3u8",
+                "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1,RESOLUTION=1280x720\n720p.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=1\nbroken.m3u8\n",
+            ),
+            (
+                "/720p.m3u8",
+                "#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:4,\nseg0.ts\n#EXTINF:4,\nseg1.ts\n",
+            ),
+            ("/broken.m3u8", "<html>not a playlist</html>"),
+            ("/init.mp4", "init"),
+            ("/seg0.ts", "segment"),
+            ("/seg1.ts", "segment"),
+        ]);
+        let sources = json!({
+            "streams": [{ "file": base.join("master.m3u8").unwrap().as_str(), "type": "hls", "quality": "auto" }],
+            "subtitles": [{ "url": base.join("en.vtt").unwrap().as_str(), "language": "English" }],
+        });
+
+        let reports = probe(&sources, None).unwrap();
+        assert_eq!(reports.len(), 2);
+
+        let stream: Vec<_> = reports[0]
+            .findings
+            .iter()
+            .map(|finding| {
+                (
+                    finding.subject.replace(base.as_str(), "/"),
+                    finding.result.is_ok(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            stream,
+            [
+                ("master playlist".to_string(), true),
+                ("variant 1280x720 /720p.m3u8".to_string(), true),
+                ("init segment /init.mp4".to_string(), true),
+                ("segment /seg0.ts".to_string(), true),
+                ("segment /seg1.ts".to_string(), true),
+                ("variant /broken.m3u8".to_string(), false),
+            ]
+        );
+        assert_eq!(reports[0].failures(), 1);
+
+        let subtitle = &reports[1].findings[0];
+        assert_eq!(
+            subtitle.result,
+            Err(format!(
+                "404 Not Found from {}",
+                base.join("en.vtt").unwrap()
+            ))
+        );
+    }
+}
@@ -0,0 +1,1487 @@
+use crate::cassette::Cassette;
+use crate::cli::Method;
+use crate::console;
+use crate::event_loop::{self, EventLoop, LoopError};
+use crate::html;
+use crate::http;
+use crate::manifest::ModuleType;
+use crate::modules::{self, Files, Format};
+use crate::source_map::{SourceMap, SourceMaps};
+use crate::timers;
+use crate::web;
+use std::fmt;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+/// JavaScript run in every context before the module, in order.
+const PRELUDE: &[&str] = &[
+    include_str!("js/console.js"),
+    include_str!("js/timers.js"),
+    include_str!("js/web.js"),
+    include_str!("js/fetch.js"),
+    include_str!("js/cheerio.js"),
+];
+
+/// A module's script and the name errors refer to it by.
+pub struct Script {
+    pub name: String,
+    pub source: String,
+    /// Maps positions in a bundled script back to its original sources.
+    pub source_map: Option<Arc<SourceMap>>,
+    pub format: Format,
+    /// The type the module's manifest declares, unknown for a bare script.
+    pub module_type: Option<ModuleType>,
+    /// The module's files, which an ES module can import.
+    pub files: Arc<Files>,
+}
+
+/// Initialises V8 for the process. Only the first call does any work.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let platform = v8::new_default_platform(0, false).make_shared();
+        v8::V8::initialize_platform(platform);
+        v8::V8::initialize();
+    });
+}
+
+/// Creates an isolate with its own event loop.
+pub fn new_isolate() -> v8::OwnedIsolate {
+    init();
+    let mut isolate = v8::Isolate::new(Default::default());
+    EventLoop::install(&mut isolate);
+    isolate
+}
+
+/// Creates a context with the host functions and the standard APIs that
+/// modules can rely on.
+pub fn new_context<'s>(scope: &mut v8::HandleScope<'s, ()>) -> v8::Local<'s, v8::Context> {
+    let context = v8::Context::new(scope);
+    let scope = &mut v8::ContextScope::new(scope, context);
+
+    // Expose the Rust console output to JavaScript, `js/console.js` builds
+    // `console` on top of it
+    let global = context.global(scope);
+    let print_callback = v8::FunctionTemplate::new(scope, console::print_handler);
+    let print_fn = print_callback.get_function(scope).unwrap();
+    let key = v8::String::new(scope, "__console_print").unwrap().into();
+    global.set(scope, key, print_fn.into());
+
+    // Expose Rust function to JavaScript
+    // Create a FunctionTemplate and get the function
+    let send_request_callback = v8::FunctionTemplate::new(scope, http::send_request_handler);
+    let send_request_fn = send_request_callback.get_function(scope).unwrap();
+
+    // Set the function in the global object
+    let key = v8::String::new(scope, "request").unwrap().into();
+    global.set(scope, key, send_request_fn.into());
+
+    // Timers on the isolate's event loop, which `js/timers.js` wraps in
+    // `setTimeout` and friends
+    let timers_object = timers::host_object(scope);
+    let key = v8::String::new(scope, "__timers").unwrap().into();
+    global.set(scope, key, timers_object.into());
+
+    // URL parsing and text encodings, which `js/web.js` builds the standard
+    // globals on
+    let web_object = web::host_object(scope);
+    let key = v8::String::new(scope, "__web").unwrap().into();
+    global.set(scope, key, web_object.into());
+
+    // HTML parsing, which `js/cheerio.js` wraps in a cheerio-like API
+    let html_object = html::host_object(scope);
+    let key = v8::String::new(scope, "__html").unwrap().into();
+    global.set(scope, key, html_object.into());
+
+    // Scripts that build the standard APIs on top of the host functions
+    for prelude in PRELUDE {
+        let code = v8::String::new(scope, prelude).unwrap();
+        let script = v8::Script::compile(scope, code, None).unwrap();
+        script.run(scope).unwrap();
+    }
+
+    context
+}
+
+/// Describes where compiled code comes from, so stack traces and errors
+/// name the file.
+pub fn origin<'s>(
+    scope: &mut v8::HandleScope<'s, ()>,
+    name: &str,
+    is_module: bool,
+) -> v8::ScriptOrigin<'s> {
+    let name = v8::String::new(scope, name).unwrap();
+    let source_map_url = v8::undefined(scope);
+    v8::ScriptOrigin::new(
+        scope,
+        name.into(),
+        0,
+        0,
+        false,
+        0,
+        source_map_url.into(),
+        false,
+        false,
+        is_module,
+    )
+}
+
+/// Adds a host function to an object, for the host objects the prelude
+/// scripts build their APIs on.
+pub fn set_function(
+    scope: &mut v8::HandleScope,
+    object: v8::Local<v8::Object>,
+    name: &str,
+    callback: impl v8::MapFnTo<v8::FunctionCallback>,
+) {
+    let template = v8::FunctionTemplate::new(scope, callback);
+    let function = template.get_function(scope).unwrap();
+    let key = v8::String::new(scope, name).unwrap();
+    object.set(scope, key.into(), function.into());
+}
+
+/// Evaluates a module script and instantiates its `default` class, either
+/// from the `source` global of a classic script or the default export of an
+/// ES module.
+pub fn load_module<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    script: &Script,
+) -> Result<v8::Local<'s, v8::Object>, InvokeError> {
+    if script.format.resolve(script) == Format::Esm {
+        return modules::load(scope, script);
+    }
+
+    let try_catch = &mut v8::TryCatch::new(scope);
+
+    let code = v8::String::new(try_catch, &script.source).unwrap();
+    let origin = origin(try_catch, &script.name, false);
+
+    let compiled = match v8::Script::compile(try_catch, code, Some(&origin)) {
+        Some(compiled) => compiled,
+        None => return Err(InvokeError::Compile(Exception::caught(try_catch))),
+    };
+
+    let instance = compiled.run(try_catch).and_then(|_| {
+        let init_code = v8::String::new(try_catch, "new source.default();").unwrap();
+        let script = v8::Script::compile(try_catch, init_code, None).unwrap();
+        script.run(try_catch)
+    });
+    let instance = match instance {
+        Some(instance) => instance,
+        None => return Err(InvokeError::Load(Exception::caught(try_catch))),
+    };
+
+    match v8::Local::<v8::Object>::try_from(instance) {
+        Ok(instance) => Ok(instance),
+        Err(_) => Err(InvokeError::NotAnObject),
+    }
+}
+
+/// Looks up an entry point on the module instance.
+pub fn method<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    instance: v8::Local<v8::Object>,
+    method: Method,
+) -> Option<v8::Local<'s, v8::Function>> {
+    let key = v8::String::new(scope, method.name()).unwrap();
+    let value = instance.get(scope, key.into())?;
+    v8::Local::<v8::Function>::try_from(value).ok()
+}
+
+/// Calls an entry point and runs the event loop until the promise it
+/// returns settles.
+pub fn call<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    instance: v8::Local<'s, v8::Object>,
+    method: v8::Local<'s, v8::Function>,
+    args: &[v8::Local<'s, v8::Value>],
+    timeout: Duration,
+) -> Result<v8::Local<'s, v8::Value>, InvokeError> {
+    let result = {
+        let try_catch = &mut v8::TryCatch::new(scope);
+        match method.call(try_catch, instance.into(), args) {
+            Some(result) => result,
+            None => return Err(InvokeError::Threw(Exception::caught(try_catch))),
+        }
+    };
+
+    // Plain return values are treated like an already resolved promise.
+    let promise = match v8::Local::<v8::Promise>::try_from(result) {
+        Ok(promise) => promise,
+        Err(_) => {
+            let resolver = v8::PromiseResolver::new(scope).unwrap();
+            resolver.resolve(scope, result);
+            resolver.get_promise(scope)
+        }
+    };
+
+    event_loop::run_until_settled(scope, promise, timeout).map_err(|err| match err {
+        LoopError::Rejected(error) => InvokeError::Rejected(Exception::new(scope, error)),
+        LoopError::TimedOut => InvokeError::TimedOut(timeout),
+        LoopError::Stalled => InvokeError::Stalled,
+    })
+}
+
+/// Why calling an entry point with `invoke` failed.
+pub enum InvokeError {
+    /// The script has a syntax error.
+    Compile(Exception),
+    /// The script threw while running or constructing its `default` class.
+    Load(Exception),
+    /// The module's `default` class did not produce an object.
+    NotAnObject,
+    /// The module does not define the entry point.
+    Missing(Method),
+    /// The entry point threw before returning its promise.
+    Threw(Exception),
+    /// The entry point's promise was rejected.
+    Rejected(Exception),
+    /// The entry point's promise did not settle before the timeout.
+    TimedOut(Duration),
+    /// The entry point's promise can never settle.
+    Stalled,
+    /// Requests that were made while replaying but are not in the cassette.
+    Unrecorded(Vec<String>),
+    /// The entry point resolved with a value that has no JSON form.
+    NotSerializable(String),
+}
+
+impl InvokeError {
+    /// The status `chouten run` exits with: 3 for compile errors, 4 for
+    /// exceptions, 5 for rejected promises, 6 for promises that never settle
+    /// and 1 for everything else. clap already uses 2 for usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InvokeError::Compile(_) => 3,
+            InvokeError::Load(_) | InvokeError::NotAnObject | InvokeError::Threw(_) => 4,
+            InvokeError::Rejected(_) => 5,
+            InvokeError::TimedOut(_) | InvokeError::Stalled => 6,
+            InvokeError::Missing(_)
+            | InvokeError::Unrecorded(_)
+            | InvokeError::NotSerializable(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for InvokeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvokeError::Compile(err) => write!(f, "Module could not be compiled: {}", err),
+            InvokeError::Load(err) => write!(f, "Module could not be loaded: {}", err),
+            InvokeError::NotAnObject => write!(
+                f,
+                "Module could not be loaded: its `default` class did not produce an object."
+            ),
+            InvokeError::Missing(method) => {
+                write!(f, "Module does not define a `{}` method.", method.name())
+            }
+            InvokeError::Threw(err) => write!(f, "Uncaught exception: {}", err),
+            InvokeError::Rejected(err) => write!(f, "Promise rejected: {}", err),
+            InvokeError::TimedOut(timeout) => write!(
+                f,
+                "Promise did not settle within {} seconds.",
+                timeout.as_secs()
+            ),
+            InvokeError::Stalled => write!(
+                f,
+                "Promise never settled: no pending work is left that could resolve it."
+            ),
+            InvokeError::Unrecorded(requests) => {
+                write!(f, "Requests not found in the cassette:")?;
+                for request in requests {
+                    write!(f, "\n  {}", request)?;
+                }
+                Ok(())
+            }
+            InvokeError::NotSerializable(reason) => {
+                write!(f, "Result could not be serialized to JSON: {}", reason)
+            }
+        }
+    }
+}
+
+/// Loads a module in a fresh isolate, calls one of its entry points and
+/// returns the JSON of the value it resolves with, or `None` for `undefined`.
+///
+/// Requests go through `cassette` when one is given. A module may catch the
+/// rejection of a replay miss, so misses fail the call even if the entry
+/// point still resolved.
+pub fn invoke(
+    script: &Script,
+    entry: Method,
+    args: &[serde_json::Value],
+    timeout: Duration,
+    cassette: Option<&Arc<Cassette>>,
+) -> Result<Option<String>, InvokeError> {
+    let isolate = &mut new_isolate();
+    if let Some(source_map) = &script.source_map {
+        source_map.install(isolate);
+    }
+    // The cassette may be shared with earlier calls, whose misses are
+    // theirs to report.
+    let mut known_misses = 0;
+    if let Some(cassette) = cassette {
+        cassette.install(isolate);
+        known_misses = cassette.misses().len();
+    }
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = new_context(scope);
+    let scope = &mut v8::ContextScope::new(scope, context);
+
+    let instance = load_module(scope, script)?;
+    let method = method(scope, instance, entry).ok_or(InvokeError::Missing(entry))?;
+
+    let args: Vec<_> = args
+        .iter()
+        .map(|arg| {
+            let json = v8::String::new(scope, &arg.to_string()).unwrap();
+            v8::json::parse(scope, json).unwrap()
+        })
+        .collect();
+
+    let result = call(scope, instance, method, &args, timeout)?;
+
+    if let Some(cassette) = cassette {
+        let misses = cassette.misses();
+        if misses.len() > known_misses {
+            return Err(InvokeError::Unrecorded(misses[known_misses..].to_vec()));
+        }
+    }
+
+    to_json(scope, result).map_err(InvokeError::NotSerializable)
+}
+
+/// Loads a module in a fresh isolate and lists the entry points its
+/// instance defines.
+pub fn defined_methods(script: &Script) -> Result<Vec<Method>, InvokeError> {
+    let isolate = &mut new_isolate();
+    if let Some(source_map) = &script.source_map {
+        source_map.install(isolate);
+    }
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = new_context(scope);
+    let scope = &mut v8::ContextScope::new(scope, context);
+
+    let instance = load_module(scope, script)?;
+    Ok(Method::ALL
+        .into_iter()
+        .filter(|&entry| method(scope, instance, entry).is_some())
+        .collect())
+}
+
+/// Serializes a value to JSON. `undefined` yields `None`, so it can be told
+/// apart from `null`.
+///
+/// Fails for values `JSON.stringify` skips, i.e. functions and symbols, and
+/// for values it throws on, such as BigInts and cycles.
+pub fn to_json(
+    scope: &mut v8::HandleScope,
+    value: v8::Local<v8::Value>,
+) -> Result<Option<String>, String> {
+    if value.is_undefined() {
+        return Ok(None);
+    }
+    if value.is_function() || value.is_symbol() {
+        let kind = value.type_of(scope).to_rust_string_lossy(scope);
+        return Err(format!("a {} has no JSON form.", kind));
+    }
+
+    let try_catch = &mut v8::TryCatch::new(scope);
+    match v8::json::stringify(try_catch, value) {
+        Some(json) => Ok(Some(json.to_rust_string_lossy(try_catch))),
+        None => Err(Exception::caught(try_catch).message),
+    }
+}
+
+/// Longest part of a source line shown under an error, so minified scripts
+/// do not flood the terminal.
+const MAX_EXCERPT_WIDTH: usize = 100;
+
+/// A thrown or rejected value and where in the script it came from.
+pub struct Exception {
+    /// `Name: message` for errors, the value itself for anything else.
+    pub message: String,
+    /// The stack frames below the message, if the value has a stack.
+    pub stack: Option<String>,
+    pub location: Option<Location>,
+}
+
+/// The place in the script an exception points at.
+pub struct Location {
+    pub file: String,
+    /// 1-based.
+    pub line: usize,
+    /// 0-based, in characters.
+    pub column: usize,
+    /// Characters from `column` the error spans, at least 1.
+    pub width: usize,
+    /// Missing when the position was mapped to a source the source map
+    /// does not embed.
+    pub source_line: Option<String>,
+}
+
+impl Exception {
+    /// The exception a `TryCatch` caught.
+    pub fn caught(try_catch: &mut v8::TryCatch<v8::HandleScope>) -> Exception {
+        let exception = match try_catch.exception() {
+            Some(exception) => exception,
+            None => {
+                return Exception {
+                    message: "Execution was terminated.".to_string(),
+                    stack: None,
+                    location: None,
+                }
+            }
+        };
+        let message = try_catch.message();
+        Exception::describe(try_catch, exception, message)
+    }
+
+    /// Describes a value that was thrown or rejected outside a `TryCatch`.
+    pub fn new(scope: &mut v8::HandleScope, value: v8::Local<v8::Value>) -> Exception {
+        let message = v8::Exception::create_message(scope, value);
+        Exception::describe(scope, value, Some(message))
+    }
+
+    fn describe(
+        scope: &mut v8::HandleScope,
+        value: v8::Local<v8::Value>,
+        message: Option<v8::Local<v8::Message>>,
+    ) -> Exception {
+        let text = value.to_rust_string_lossy(scope);
+
+        // `stack` repeats the message before the frames.
+        let mut stack = None;
+        if let Ok(object) = v8::Local::<v8::Object>::try_from(value) {
+            let key = v8::String::new(scope, "stack").unwrap();
+            if let Some(value) = object.get(scope, key.into()) {
+                if value.is_string() {
+                    let mut value = value.to_rust_string_lossy(scope);
+                    if let Some(source_map) = SourceMaps::get(scope) {
+                        value = source_map.remap(&value);
+                    }
+                    let frames = value.strip_prefix(text.as_str()).unwrap_or(&value);
+                    let frames = frames.trim_start_matches('\n');
+                    if !frames.is_empty() {
+                        stack = Some(frames.to_string());
+                    }
+                }
+            }
+        }
+
+        Exception {
+            message: text,
+            stack,
+            location: message.and_then(|message| Location::new(scope, message)),
+        }
+    }
+}
+
+impl Location {
+    fn new(scope: &mut v8::HandleScope, message: v8::Local<v8::Message>) -> Option<Location> {
+        let file = message.get_script_resource_name(scope)?;
+        if !file.is_string() {
+            return None;
+        }
+        let file = file.to_rust_string_lossy(scope);
+        let line = message.get_line_number(scope)?;
+        let source_line = message.get_source_line(scope)?.to_rust_string_lossy(scope);
+        let column = message.get_start_column();
+        let width = message.get_end_column().saturating_sub(column).max(1);
+
+        let original = SourceMaps::get(scope)
+            .and_then(|source_map| source_map.lookup(&file, line, column + 1));
+        if let Some(position) = original {
+            return Some(Location {
+                file: position.file,
+                line: position.line,
+                column: position.column - 1,
+                width,
+                source_line: position.source_line,
+            });
+        }
+
+        Some(Location {
+            file,
+            line,
+            column,
+            width,
+            source_line: Some(source_line),
+        })
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(stack) = &self.stack {
+            write!(f, "\n{}", stack)?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, "\n{}", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Location {
+    /// Renders the position followed by the source line with the span
+    /// underlined, cut down to a window around it for long lines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "  --> {}:{}:{}", self.file, self.line, self.column + 1)?;
+        let source_line = match &self.source_line {
+            Some(source_line) => source_line,
+            None => return Ok(()),
+        };
+
+        let chars: Vec<char> = source_line.chars().collect();
+        let column = self.column.min(chars.len());
+        let start = column.saturating_sub(MAX_EXCERPT_WIDTH / 2);
+        let end = (start + MAX_EXCERPT_WIDTH).min(chars.len());
+        let width = self.width.min(end.saturating_sub(column)).max(1);
+
+        let mut excerpt: String = chars[start..end].iter().collect();
+        let mut padding: String = chars[start..column]
+            .iter()
+            .map(|&c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        if start > 0 {
+            excerpt.insert_str(0, "...");
+            padding.insert_str(0, "   ");
+        }
+        if end < chars.len() {
+            excerpt.push_str("...");
+        }
+
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        writeln!(f)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", number, excerpt)?;
+        write!(f, "{} | {}{}", gutter, padding, "^".repeat(width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive;
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::sync::Once;
+    use std::thread;
+    use std::time::Instant;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Writes `files` into a fresh module directory and calls `method` on the
+    /// module in `entry`.
+    fn invoke_files(
+        name: &str,
+        files: &[(&str, &str)],
+        entry: &str,
+        method: Method,
+        args: &[Value],
+        timeout: Duration,
+    ) -> Result<Option<Value>, InvokeError> {
+        let dir = module_dir(name);
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let script = archive::read_script(&dir, entry).unwrap();
+        let result = invoke(&script, method, args, timeout, None);
+        fs::remove_dir_all(&dir).unwrap();
+        result.map(|json| json.map(|json| serde_json::from_str(&json).unwrap()))
+    }
+
+    fn module_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chouten-runtime-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs an ES module and returns what `discover` resolves with.
+    fn discover(name: &str, source: &str) -> Value {
+        let files = [("code.js", source)];
+        match invoke_files(name, &files, "code.js", Method::Discover, &[], TIMEOUT) {
+            Ok(value) => value.unwrap_or(Value::Null),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Runs an ES module whose `discover` is expected to fail.
+    fn discover_err(name: &str, source: &str) -> InvokeError {
+        let files = [("code.js", source)];
+        match invoke_files(name, &files, "code.js", Method::Discover, &[], TIMEOUT) {
+            Ok(value) => panic!("expected an error, got {:?}", value),
+            Err(err) => err,
+        }
+    }
+
+    /// Runs an ES module and returns what `discover` resolves with when it is
+    /// passed the url of a server started with `serve`.
+    fn discover_served(name: &str, source: &str) -> Value {
+        let files = [("code.js", source)];
+        let args = [json!(serve())];
+        match invoke_files(name, &files, "code.js", Method::Discover, &args, TIMEOUT) {
+            Ok(value) => value.unwrap_or(Value::Null),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Serves requests on a local port for the rest of the test run and
+    /// returns its url. Each connection is answered on its own thread:
+    ///
+    /// - `/binary` with bytes that are not valid UTF-8,
+    /// - `/missing` with a 404,
+    /// - `/slow/<ms>` with `slow`, after waiting that long,
+    /// - anything else with JSON echoing the method, path, headers and body.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                thread::spawn(move || respond(stream));
+            }
+        });
+        url
+    }
+
+    fn respond(mut stream: TcpStream) {
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut request_line = line.split_whitespace();
+        let method = request_line.next().unwrap().to_string();
+        let path = request_line.next().unwrap().to_string();
+
+        let mut headers = serde_json::Map::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some((name, value)) => headers.insert(name.to_ascii_lowercase(), json!(value)),
+                None => break,
+            };
+        }
+        let length = headers
+            .get("content-length")
+            .and_then(Value::as_str)
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let route = path.split('?').next().unwrap();
+        let (status, content_type, body) = if route == "/binary" {
+            (
+                "200 OK",
+                "application/octet-stream",
+                vec![0x00, 0xff, 0xfe, 0x80],
+            )
+        } else if route == "/missing" {
+            ("404 Not Found", "text/plain", b"not found".to_vec())
+        } else if let Some(delay) = route.strip_prefix("/slow/") {
+            thread::sleep(Duration::from_millis(delay.parse().unwrap()));
+            ("200 OK", "text/plain", b"slow".to_vec())
+        } else {
+            let echo = json!({
+                "method": method,
+                "path": path,
+                "headers": headers,
+                "body": String::from_utf8_lossy(&body),
+            });
+            ("200 OK", "application/json", echo.to_string().into_bytes())
+        };
+
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).unwrap();
+        if method != "HEAD" {
+            stream.write_all(&body).unwrap();
+        }
+    }
+
+    /// The console lines containing `marker`. Console output of every test
+    /// goes to one log file, which is set up by the first call.
+    fn logged(marker: &str) -> Vec<String> {
+        static LOG_FILE: Once = Once::new();
+        let path = std::env::temp_dir().join(format!("chouten-runtime-{}.log", std::process::id()));
+        LOG_FILE.call_once(|| {
+            let _ = fs::remove_file(&path);
+            console::set_output(Some(&path), false).unwrap();
+        });
+        fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains(marker))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn fake_time_moves_date_and_performance_with_timers() {
+        event_loop::set_fake_time(true);
+        let started = Instant::now();
+        let elapsed = discover(
+            "fake-time",
+            r#"
+            export default class {
+                async discover() {
+                    const date = Date.now();
+                    const now = performance.now();
+                    const created = new Date().getTime();
+                    await new Promise((resolve) => setTimeout(resolve, 10000));
+                    return [
+                        Date.now() - date,
+                        performance.now() - now,
+                        new Date().getTime() - created,
+                        new Date(0).getTime(),
+                        new Date() instanceof Date,
+                    ];
+                }
+            }
+            "#,
+        );
+        event_loop::set_fake_time(false);
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let elapsed = elapsed.as_array().unwrap();
+        for value in &elapsed[..3] {
+            let value = value.as_f64().unwrap();
+            assert!((10000.0..11000.0).contains(&value), "{}", value);
+        }
+        assert_eq!(elapsed[3..], [json!(0), json!(true)]);
+    }
+
+    #[test]
+    fn settles_after_microtasks_and_timers() {
+        let value = discover(
+            "settles",
+            r#"
+            export default class {
+                async discover() {
+                    let total = 0;
+                    for (let i = 0; i < 100; i++) {
+                        total += await Promise.resolve(i);
+                    }
+                    const timer = await new Promise((resolve) => {
+                        setTimeout(() => resolve("timer"), 10);
+                    });
+                    return [total, timer];
+                }
+            }
+            "#,
+        );
+        assert_eq!(value, json!([4950, "timer"]));
+    }
+
+    #[test]
+    fn plain_and_undefined_results() {
+        let source = r#"
+            export default class {
+                discover() { return { plain: true }; }
+                async search() {}
+            }
+        "#;
+        let files = [("code.js", source)];
+        let plain = invoke_files("plain", &files, "code.js", Method::Discover, &[], TIMEOUT);
+        assert_eq!(plain.ok(), Some(Some(json!({ "plain": true }))));
+        let undefined = invoke_files("undefined", &files, "code.js", Method::Search, &[], TIMEOUT);
+        assert_eq!(undefined.ok(), Some(None));
+    }
+
+    #[test]
+    fn reports_promises_that_can_never_settle() {
+        let started = Instant::now();
+        let err = discover_err(
+            "stalled",
+            "export default class { discover() { return new Promise(() => {}); } }",
+        );
+        assert!(matches!(err, InvokeError::Stalled), "{}", err);
+        assert_eq!(err.exit_code(), 6);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn times_out_while_work_is_pending() {
+        let source = r#"
+            export default class {
+                discover() {
+                    return new Promise(() => setInterval(() => {}, 10));
+                }
+            }
+        "#;
+        let started = Instant::now();
+        let timeout = Duration::from_millis(200);
+        let files = [("code.js", source)];
+        match invoke_files(
+            "timed-out",
+            &files,
+            "code.js",
+            Method::Discover,
+            &[],
+            timeout,
+        ) {
+            Err(err @ InvokeError::TimedOut(_)) => assert_eq!(err.exit_code(), 6),
+            Err(err) => panic!("{}", err),
+            Ok(value) => panic!("expected a timeout, got {:?}", value),
+        }
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn fetch_wraps_responses_and_headers() {
+        let value = discover_served(
+            "fetch",
+            r#"
+            export default class {
+                async discover(base) {
+                    const response = await fetch(`${base}/echo`, {
+                        method: "post",
+                        headers: { "X-Custom": "a" },
+                        body: JSON.stringify({ q: 1 }),
+                    });
+                    const echo = await response.json();
+                    const used = await response.text().then(() => null, (error) => error.name);
+                    const binary = await fetch(`${base}/binary`);
+                    const missing = await fetch(`${base}/missing`);
+                    const refused = await fetch("http://127.0.0.1:1/").then(
+                        () => null,
+                        (error) => error.name,
+                    );
+
+                    const headers = new Headers([["B", "2"], ["a", "1"]]);
+                    headers.append("A", "3");
+                    const made = new Response("made", { status: 201, headers });
+
+                    return {
+                        status: response.status,
+                        ok: response.ok,
+                        type: response.headers.get("Content-Type"),
+                        echo: [echo.method, echo.headers["x-custom"], echo.body],
+                        used,
+                        bytes: [...new Uint8Array(await binary.arrayBuffer())],
+                        missing: [missing.status, missing.ok, await missing.text()],
+                        refused,
+                        headers: [...headers],
+                        made: [made.status, made.headers.get("a"), await made.text()],
+                    };
+                }
+            }
+            "#,
+        );
+        assert_eq!(
+            value,
+            json!({
+                "status": 200,
+                "ok": true,
+                "type": "application/json",
+                "echo": ["POST", "a", "{\"q\":1}"],
+                "used": "TypeError",
+                "bytes": [0, 255, 254, 128],
+                "missing": [404, false, "not found"],
+                "refused": "TypeError",
+                "headers": [["a", "1, 3"], ["b", "2"]],
+                "made": [201, "1, 3", "made"],
+            })
+        );
+    }
+
+    #[test]
+    fn request_forwards_methods_headers_and_bodies() {
+        let value = discover_served(
+            "request",
+            r#"
+            export default class {
+                async discover(base) {
+                    const echo = async (...args) => JSON.parse((await request(...args)).body);
+                    const form = await echo(
+                        `${base}/echo`,
+                        "POST",
+                        { "Content-Type": "application/x-www-form-urlencoded" },
+                        { q: "a b", page: 2 },
+                    );
+                    const json = await echo(
+                        `${base}/echo`,
+                        "PUT",
+                        { "User-Agent": "chouten-test", Referer: "https://example.com/" },
+                        { q: [1] },
+                    );
+                    const bytes = await echo(`${base}/echo`, "PATCH", {}, new Uint8Array([104, 105]));
+                    const methods = [];
+                    for (const method of ["DELETE", "OPTIONS", "post"]) {
+                        methods.push((await echo(`${base}/echo`, method)).method);
+                    }
+                    const head = await request(`${base}/echo`, "HEAD");
+
+                    const thrown = (...args) => {
+                        try {
+                            request(...args);
+                        } catch (error) {
+                            return `${error.name}: ${error.message}`;
+                        }
+                    };
+                    return {
+                        form: [form.body, form.headers["content-type"]],
+                        json: [
+                            json.method,
+                            json.body,
+                            json.headers["content-type"],
+                            json.headers["user-agent"],
+                            json.headers.referer,
+                        ],
+                        bytes: bytes.body,
+                        methods,
+                        head: [head.statusCode, head.body],
+                        unsupported: thrown(base, "BREW"),
+                        getBody: thrown(base, "GET", {}, "x"),
+                    };
+                }
+            }
+            "#,
+        );
+        assert_eq!(
+            value,
+            json!({
+                "form": ["q=a+b&page=2", "application/x-www-form-urlencoded"],
+                "json": [
+                    "PUT",
+                    "{\"q\":[1]}",
+                    "application/json",
+                    "chouten-test",
+                    "https://example.com/",
+                ],
+                "bytes": "hi",
+                "methods": ["DELETE", "OPTIONS", "POST"],
+                "head": [200, ""],
+                "unsupported": "TypeError: Unsupported method: BREW",
+                "getBody": "TypeError: Request with GET method cannot have a body.",
+            })
+        );
+    }
+
+    #[test]
+    fn requests_run_in_parallel_with_timers() {
+        let value = discover_served(
+            "parallel",
+            r#"
+            export default class {
+                async discover(base) {
+                    let ticks = 0;
+                    const interval = setInterval(() => ticks++, 20);
+                    const started = Date.now();
+                    const bodies = await Promise.all(
+                        [1, 2, 3, 4].map((i) => request(`${base}/slow/300?${i}`).then((r) => r.body)),
+                    );
+                    clearInterval(interval);
+                    return { bodies, elapsed: Date.now() - started, ticks };
+                }
+            }
+            "#,
+        );
+        assert_eq!(value["bodies"], json!(["slow", "slow", "slow", "slow"]));
+        // One after the other, the requests would take 1200ms.
+        let elapsed = value["elapsed"].as_f64().unwrap();
+        assert!(elapsed < 900.0, "{}", elapsed);
+        // The isolate kept running timers while it waited.
+        let ticks = value["ticks"].as_f64().unwrap();
+        assert!(ticks >= 5.0, "{}", ticks);
+    }
+
+    #[test]
+    fn console_formats_messages_and_filters_levels() {
+        logged("console-test");
+        discover(
+            "console",
+            r#"
+            export default class {
+                discover() {
+                    console.log("console-test %s is %d years, %o", "Ann", 42.5, { tags: ["a"] });
+                    console.log(
+                        "console-test",
+                        { nested: { deep: { deeper: { deepest: 1 } } } },
+                        [1, "two"],
+                        new Map([["k", 1]]),
+                    );
+                    console.debug("console-test debug");
+                    console.info("console-test %i%% %j", 7.9, { a: 1 });
+                    console.warn("console-test warn");
+                    console.error("console-test error", new Set([1]));
+                    console.group("console-test group");
+                    console.log("console-test inside");
+                    console.groupEnd();
+                    console.count("console-test");
+                    console.count("console-test");
+                    console.assert(true, "console-test passed");
+                    console.assert(false, "console-test %s", "assertion");
+                }
+            }
+            "#,
+        );
+        // `debug` is below the default level.
+        assert_eq!(
+            logged("console-test"),
+            [
+                "console-test Ann is 42.5 years, { tags: [ 'a' ] }",
+                "console-test { nested: { deep: { deeper: [Object] } } } [ 1, 'two' ] Map(1) { 'k' => 1 }",
+                "console-test 7% {\"a\":1}",
+                "console-test warn",
+                "console-test error Set(1) { 1 }",
+                "console-test group",
+                "  console-test inside",
+                "console-test: 1",
+                "console-test: 2",
+                "Assertion failed: console-test assertion",
+            ]
+        );
+    }
+
+    /// Checks that an exception points at `line` of the module's `code.js`.
+    fn assert_location(exception: &Exception, line: usize, source_line: &str) {
+        let location = exception.location.as_ref().unwrap();
+        assert!(location.file.ends_with("code.js"), "{}", location.file);
+        assert_eq!(location.line, line);
+        assert_eq!(location.source_line.as_deref(), Some(source_line));
+    }
+
+    #[test]
+    fn compile_errors_point_at_the_source() {
+        let err = discover_err(
+            "compile-error",
+            "export default class {\n  discover() {\n    return 1 +;\n  }\n}\n",
+        );
+        assert_eq!(err.exit_code(), 3);
+        match err {
+            InvokeError::Compile(exception) => {
+                assert!(
+                    exception.message.starts_with("SyntaxError: "),
+                    "{}",
+                    exception
+                );
+                assert_location(&exception, 3, "    return 1 +;");
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn load_errors_point_at_the_source() {
+        let err = discover_err(
+            "load-error",
+            "throw new TypeError(\"bad load\");\nexport default class {}\n",
+        );
+        assert_eq!(err.exit_code(), 4);
+        match err {
+            InvokeError::Load(exception) => {
+                assert_eq!(exception.message, "TypeError: bad load");
+                assert_location(&exception, 1, "throw new TypeError(\"bad load\");");
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn thrown_exceptions_point_at_the_source() {
+        let err = discover_err(
+            "threw",
+            "export default class {\n  discover() {\n    const value = null;\n    return value.name;\n  }\n}\n",
+        );
+        assert_eq!(err.exit_code(), 4);
+        assert!(err.to_string().contains("code.js:4:"), "{}", err);
+        match err {
+            InvokeError::Threw(exception) => {
+                assert_eq!(
+                    exception.message,
+                    "TypeError: Cannot read properties of null (reading 'name')"
+                );
+                assert!(exception.stack.as_ref().unwrap().contains("code.js:4:"));
+                assert_location(&exception, 4, "    return value.name;");
+            }
+            err => panic!("{}", err),
+        }
+
+        // Thrown values that are not errors have no stack.
+        let err = discover_err(
+            "threw-string",
+            "var source = { default: class { discover() { throw \"plain\"; } } };",
+        );
+        match err {
+            InvokeError::Threw(exception) => {
+                assert_eq!(exception.message, "plain");
+                assert!(exception.stack.is_none());
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn rejections_point_at_the_source() {
+        let err = discover_err(
+            "rejected",
+            "export default class {\n  async discover() {\n    await null;\n    throw new RangeError(\"nope\");\n  }\n}\n",
+        );
+        assert_eq!(err.exit_code(), 5);
+        match err {
+            InvokeError::Rejected(exception) => {
+                assert_eq!(exception.message, "RangeError: nope");
+                assert!(exception.stack.as_ref().unwrap().contains("code.js:4:"));
+                assert_location(&exception, 4, "    throw new RangeError(\"nope\");");
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn missing_entry_points_exit_with_1() {
+        let files = [("code.js", "export default class { discover() {} }")];
+        match invoke_files("missing", &files, "code.js", Method::Search, &[], TIMEOUT) {
+            Err(err @ InvokeError::Missing(Method::Search)) => assert_eq!(err.exit_code(), 1),
+            Err(err) => panic!("{}", err),
+            Ok(value) => panic!("expected an error, got {:?}", value),
+        }
+    }
+
+    /// A bundle whose lines 3 and 4 come from lines 7 and 8 of `MAPPED`.
+    const BUNDLE: &str = r#"export default class {
+  async discover() {
+    console.log("source-map-test", new Error("logged"));
+    throw new Error("boom");
+  }
+}
+"#;
+
+    const MAPPED: &str = r#"interface Show {
+  title: string;
+}
+
+export default class {
+  async discover(): Promise<Show[]> {
+    console.log("source-map-test", new Error("logged"));
+    throw new Error("boom");
+  }
+}
+"#;
+
+    fn bundle_source_map() -> Vec<u8> {
+        let mut builder = sourcemap::SourceMapBuilder::new(Some("bundle.js"));
+        let source = builder.add_source("src/mapped.ts");
+        builder.set_source_contents(source, Some(MAPPED));
+        builder.add(2, 0, 6, 4, Some("src/mapped.ts"), None, false);
+        builder.add(3, 0, 7, 4, Some("src/mapped.ts"), None, false);
+        let mut json = Vec::new();
+        builder.into_sourcemap().to_writer(&mut json).unwrap();
+        json
+    }
+
+    #[test]
+    fn source_maps_remap_exceptions_and_console_messages() {
+        logged("src/mapped.ts");
+        let map = String::from_utf8(bundle_source_map()).unwrap();
+        let files = [("bundle.js", BUNDLE), ("bundle.js.map", map.as_str())];
+        let err = match invoke_files(
+            "source-map",
+            &files,
+            "bundle.js",
+            Method::Discover,
+            &[],
+            TIMEOUT,
+        ) {
+            Err(InvokeError::Rejected(exception)) => exception,
+            Err(err) => panic!("{}", err),
+            Ok(value) => panic!("expected a rejection, got {:?}", value),
+        };
+
+        assert_eq!(err.message, "Error: boom");
+        assert!(
+            err.stack.as_ref().unwrap().contains("src/mapped.ts:8:5"),
+            "{}",
+            err
+        );
+        let location = err.location.unwrap();
+        assert_eq!(location.file, "src/mapped.ts");
+        assert_eq!(location.line, 8);
+        assert_eq!(location.column, 4);
+        assert_eq!(
+            location.source_line.as_deref(),
+            Some("    throw new Error(\"boom\");")
+        );
+
+        // The stack of the logged error is remapped too.
+        assert_eq!(logged("src/mapped.ts:7:5").len(), 1);
+    }
+
+    #[test]
+    fn resolves_relative_imports() {
+        let files = [
+            (
+                "code.js",
+                r#"
+                import { greet } from "./lib/greet.js";
+                import helpers from "./lib";
+                import * as data from "./data.mjs";
+                import { loaded } from "./counter";
+                export default class {
+                    discover() {
+                        return [greet("a"), helpers.name, data.value, loaded()];
+                    }
+                }
+                "#,
+            ),
+            (
+                "lib/greet.js",
+                r#"
+                import { suffix } from "../suffix";
+                import "../counter.js";
+                export const greet = (name) => `hi ${name}${suffix}`;
+                "#,
+            ),
+            ("lib/index.js", r#"export default { name: "index" };"#),
+            ("suffix.js", r#"export const suffix = "!";"#),
+            ("data.mjs", "export const value = 3;"),
+            // Imported twice, but evaluated once.
+            (
+                "counter.js",
+                "let count = 0; export const loaded = () => count; count++;",
+            ),
+        ];
+        let value = invoke_files("imports", &files, "code.js", Method::Discover, &[], TIMEOUT);
+        match value {
+            Ok(value) => assert_eq!(value, Some(json!(["hi a!", "index", 3, 1]))),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_imports_it_cannot_resolve() {
+        let cases = [
+            (
+                "lodash",
+                "Error: Cannot import \"lodash\": only relative imports inside the module are supported.",
+            ),
+            (
+                "../outside.js",
+                "Error: Cannot import \"../outside.js\": it is outside the module.",
+            ),
+            ("./missing", "Error: Cannot import \"./missing\": "),
+        ];
+        for (specifier, message) in cases {
+            let source = format!(
+                "import value from {:?};\nexport default class {{ discover() {{ return value; }} }}",
+                specifier
+            );
+            let err = discover_err("unresolved-import", &source);
+            assert_eq!(err.exit_code(), 3);
+            match err {
+                InvokeError::Compile(exception) => {
+                    assert!(exception.message.starts_with(message), "{}", exception)
+                }
+                err => panic!("{}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn runs_typescript_entries_and_imports() {
+        let files = [
+            (
+                "code.ts",
+                r#"import { double } from "./math";
+
+interface Result {
+    n: number;
+}
+
+export default class {
+    discover(): Result {
+        return { n: double(21) };
+    }
+
+    async search(query: string): Promise<Result[]> {
+        throw new Error(`no results for ${query}`);
+    }
+}
+"#,
+            ),
+            (
+                "math.ts",
+                "export const double = (n: number): number => n * 2;",
+            ),
+        ];
+        let value = invoke_files(
+            "typescript",
+            &files,
+            "code.ts",
+            Method::Discover,
+            &[],
+            TIMEOUT,
+        );
+        match value {
+            Ok(value) => assert_eq!(value, Some(json!({ "n": 42 }))),
+            Err(err) => panic!("{}", err),
+        }
+
+        // Errors point at the TypeScript source, not the transpiled output.
+        let args = [json!("x")];
+        match invoke_files(
+            "typescript-error",
+            &files,
+            "code.ts",
+            Method::Search,
+            &args,
+            TIMEOUT,
+        ) {
+            Err(InvokeError::Rejected(exception)) => {
+                assert_eq!(exception.message, "Error: no results for x");
+                let location = exception.location.unwrap();
+                assert!(location.file.ends_with("code.ts"), "{}", location.file);
+                assert_eq!(location.line, 13);
+                assert_eq!(
+                    location.source_line.as_deref(),
+                    Some("        throw new Error(`no results for ${query}`);")
+                );
+            }
+            Err(err) => panic!("{}", err),
+            Ok(value) => panic!("expected a rejection, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn cheerio_traverses_documents() {
+        let value = discover(
+            "cheerio",
+            r##"
+            export default class {
+                discover() {
+                    const $ = cheerio.load(`<html><body>
+                        <ul id="list">
+                            <li class="item first" data-id="1"><a href="/one">One</a></li>
+                            <li class="item" data-id="2"><a href="/two">Two <b>2</b></a></li>
+                            <li class="other">Three</li>
+                        </ul>
+                    </body></html>`);
+                    const items = $("li.item");
+                    const invalid = () => {
+                        try {
+                            $("li[");
+                        } catch (error) {
+                            return error.name;
+                        }
+                    };
+                    return {
+                        count: items.length,
+                        texts: items.map((i, el) => $(el).text()).get(),
+                        hrefs: $("a").map((i, el) => $(el).attr("href")).get(),
+                        ids: items.map((i, el) => $(el).data("id")).get(),
+                        first: $("li").first().hasClass("first"),
+                        last: $("li").last().text(),
+                        parent: $("b").parent().attr("href"),
+                        closest: $("b").closest("li").data("id"),
+                        parents: $("b").parents().map((i, el) => el.name).get(),
+                        next: $("li.first").next().data("id"),
+                        siblings: $("li.first").siblings().length,
+                        filtered: $("li").filter(".other").text(),
+                        not: $("li").not(".other").length,
+                        is: $("li").eq(1).is("[data-id='2']"),
+                        find: $("#list").find("b").text(),
+                        children: $("#list").children().length,
+                        html: $("li").eq(1).html(),
+                        outer: $.html("b"),
+                        fragment: $('<p class="made">made</p>').attr("class"),
+                        same: $("li")[0] === $("li.first")[0],
+                        invalid: invalid(),
+                    };
+                }
+            }
+            "##,
+        );
+        assert_eq!(
+            value,
+            json!({
+                "count": 2,
+                "texts": ["One", "Two 2"],
+                "hrefs": ["/one", "/two"],
+                "ids": ["1", "2"],
+                "first": true,
+                "last": "Three",
+                "parent": "/two",
+                "closest": "2",
+                "parents": ["a", "li", "ul", "body", "html"],
+                "next": "2",
+                "siblings": 2,
+                "filtered": "Three",
+                "not": 2,
+                "is": true,
+                "find": "2",
+                "children": 3,
+                "html": "<a href=\"/two\">Two <b>2</b></a>",
+                "outer": "<b>2</b>",
+                "fragment": "made",
+                "same": true,
+                "invalid": "SyntaxError",
+            })
+        );
+    }
+
+    #[test]
+    fn timers_run_in_order() {
+        // On the virtual clock, so timers that are due close together cannot
+        // swap places on a busy machine.
+        event_loop::set_fake_time(true);
+        let order = discover(
+            "timer-order",
+            r#"
+            export default class {
+                async discover() {
+                    const order = [];
+                    await new Promise((resolve) => {
+                        setTimeout(() => order.push("timeout 25"), 25);
+                        setTimeout(() => order.push("timeout 0"), 0);
+                        setTimeout((label) => order.push(label), 0, "timeout 0 with argument");
+                        clearTimeout(setTimeout(() => order.push("cleared"), 5));
+                        queueMicrotask(() => order.push("microtask"));
+                        Promise.resolve().then(() => order.push("promise"));
+                        let ticks = 0;
+                        const interval = setInterval(() => {
+                            order.push(`interval ${++ticks}`);
+                            if (ticks === 3) {
+                                clearInterval(interval);
+                                setTimeout(resolve, 50);
+                            }
+                        }, 10);
+                        order.push("sync");
+                    });
+                    return order;
+                }
+            }
+            "#,
+        );
+        event_loop::set_fake_time(false);
+
+        assert_eq!(
+            order,
+            json!([
+                "sync",
+                "microtask",
+                "promise",
+                "timeout 0",
+                "timeout 0 with argument",
+                "interval 1",
+                "interval 2",
+                "timeout 25",
+                "interval 3",
+            ])
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use clap::ValueEnum;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CODE_TEMPLATE: &str = include_str!("templates/code.js");
+const TESTS_TEMPLATE: &str = include_str!("templates/tests.toml");
+const ICON: &[u8] = include_bytes!("templates/icon.png");
+
+/// The kinds of module `chouten new` can generate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    /// Anime, shows and movies, played from stream sources
+    Video,
+    /// Comics read page by page
+    Manga,
+    /// Books read chapter by chapter
+    Novel,
+}
+
+impl Template {
+    /// The module type recorded in the manifest.
+    pub fn module_type(self) -> &'static str {
+        match self {
+            Template::Video => "video",
+            Template::Manga => "manga",
+            Template::Novel => "novel",
+        }
+    }
+
+    fn media_type(self) -> &'static str {
+        match self {
+            Template::Video => "Episodes",
+            Template::Manga | Template::Novel => "Chapters",
+        }
+    }
+
+    fn media_description(self) -> &'static str {
+        match self {
+            Template::Video => "Episodes of the title",
+            Template::Manga | Template::Novel => "Chapters of the title",
+        }
+    }
+
+    fn sample_query(self) -> &'static str {
+        match self {
+            Template::Video => "one piece",
+            Template::Manga => "berserk",
+            Template::Novel => "omniscient reader",
+        }
+    }
+
+    /// What `sources` resolves with, documented above the stub. Only video
+    /// sources have a schema, see `schema::validate`.
+    fn sources(self) -> (&'static str, &'static str) {
+        match self {
+            Template::Video => (
+                "{ streams: [{ file, type, quality }], subtitles: [{ url, language }], skips: [], headers: {} }",
+                "{ streams: [], subtitles: [], skips: [], headers: {} }",
+            ),
+            Template::Manga => ("Pages of the chapter in reading order: [url]", "[]"),
+            Template::Novel => ("The chapter's text as HTML: string", "\"\""),
+        }
+    }
+}
+
+/// What `chouten new` should put into the manifest.
+pub struct Options {
+    pub name: String,
+    pub id: Option<String>,
+    /// Defaults to `git config user.name`, then the login name.
+    pub author: Option<String>,
+    pub base_url: String,
+    pub template: Template,
+}
+
+/// Writes a new module into `dir` and returns the files it created.
+///
+/// Refuses to touch a directory that already has files in it unless `force`
+/// is set.
+pub fn create(dir: &Path, options: &Options, force: bool) -> Result<Vec<PathBuf>, String> {
+    let occupied = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+    if occupied && !force {
+        return Err(format!(
+            "{} already exists and is not empty, pass --force to overwrite it.",
+            dir.display()
+        ));
+    }
+
+    let id = options.id.clone().unwrap_or_else(|| slug(&options.name));
+    let author = match options.author.clone().or_else(default_author) {
+        Some(author) => author,
+        None => return Err("Could not tell who the author is, pass --author.".to_string()),
+    };
+    let manifest = json!({
+        "id": id,
+        "name": options.name,
+        "version": "0.1.0",
+        "author": author,
+        "description": "",
+        "icon": "icon.png",
+        "script": "code.js",
+        "language": "en",
+        "type": options.template.module_type(),
+        "baseUrl": options.base_url,
+        "features": ["discover", "search", "info", "media", "servers", "sources"],
+    });
+
+    let (sources_description, sources) = options.template.sources();
+    let code = CODE_TEMPLATE
+        .replace("{{name}}", &options.name)
+        .replace("{{sources_description}}", sources_description)
+        .replace("{{sources}}", sources)
+        .replace("{{base_url}}", &quote(&options.base_url))
+        .replace("{{media_type}}", &quote(options.template.media_type()))
+        .replace(
+            "{{media_description}}",
+            options.template.media_description(),
+        );
+    let tests = TESTS_TEMPLATE.replace("{{query}}", &quote(options.template.sample_query()));
+
+    let files: [(&str, Vec<u8>); 4] = [
+        (
+            "metadata.json",
+            (serde_json::to_string_pretty(&manifest).unwrap() + "\n").into_bytes(),
+        ),
+        ("code.js", code.into_bytes()),
+        ("icon.png", ICON.to_vec()),
+        ("tests/module.toml", tests.into_bytes()),
+    ];
+
+    let mut created = Vec::with_capacity(files.len());
+    for (name, contents) in files {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Could not create {}: {}", parent.display(), err))?;
+        }
+        fs::write(&path, contents)
+            .map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+        created.push(path);
+    }
+
+    Ok(created)
+}
+
+/// The user name git commits with, or else the login name.
+fn default_author() -> Option<String> {
+    let from_git = Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    from_git
+        .into_iter()
+        .chain(std::env::var("USER").ok())
+        .chain(std::env::var("USERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())
+}
+
+/// Turns a display name into something usable as an id or directory name.
+pub fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Quotes a string for use in the JavaScript and TOML templates, whose
+/// string syntax agrees with JSON for everything we generate.
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
@@ -0,0 +1,576 @@
+use crate::cli::Method;
+use crate::manifest::ModuleType;
+use serde_json::Value;
+use std::fmt;
+
+/// The shape a JSON value is expected to have.
+pub enum Schema {
+    String {
+        non_empty: bool,
+    },
+    /// An absolute http(s) url.
+    Url,
+    Number,
+    Integer {
+        min: i64,
+        max: i64,
+    },
+    Bool,
+    /// One of a fixed set of strings.
+    Enum(&'static [&'static str]),
+    Array(Box<Schema>),
+    Object(Vec<Field>),
+    /// An object with arbitrary keys whose values all match the schema.
+    Map(Box<Schema>),
+    /// `null` or a value matching the schema.
+    Nullable(Box<Schema>),
+}
+
+pub struct Field {
+    name: &'static str,
+    required: bool,
+    schema: Schema,
+}
+
+/// A place where a value does not match its schema.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+fn required(name: &'static str, schema: Schema) -> Field {
+    Field {
+        name,
+        required: true,
+        schema,
+    }
+}
+
+fn optional(name: &'static str, schema: Schema) -> Field {
+    Field {
+        name,
+        required: false,
+        schema,
+    }
+}
+
+fn string() -> Schema {
+    Schema::String { non_empty: false }
+}
+
+fn non_empty() -> Schema {
+    Schema::String { non_empty: true }
+}
+
+fn integer(min: i64) -> Schema {
+    Schema::Integer { min, max: i64::MAX }
+}
+
+fn array(items: Schema) -> Schema {
+    Schema::Array(Box::new(items))
+}
+
+fn nullable(schema: Schema) -> Schema {
+    Schema::Nullable(Box::new(schema))
+}
+
+fn titles() -> Schema {
+    Schema::Object(vec![
+        required("primary", non_empty()),
+        optional("secondary", nullable(string())),
+    ])
+}
+
+/// A title as listed by `discover` and `search`.
+fn listing() -> Vec<Field> {
+    vec![
+        required("url", non_empty()),
+        required("titles", titles()),
+        required("poster", Schema::Url),
+        optional("indicator", nullable(string())),
+        optional("current", nullable(integer(0))),
+        optional("total", nullable(integer(0))),
+    ]
+}
+
+/// The schema of the value an entry point resolves with.
+pub fn result_schema(method: Method) -> Schema {
+    match method {
+        Method::Discover => {
+            let mut data = listing();
+            data.push(required("description", string()));
+
+            array(Schema::Object(vec![
+                required("title", non_empty()),
+                // Carousel, 2x grid, 3x grid and 4x grid in the app.
+                required("type", Schema::Integer { min: 0, max: 3 }),
+                required("data", array(Schema::Object(data))),
+            ]))
+        }
+        Method::Search => Schema::Object(vec![
+            required("info", Schema::Object(vec![required("pages", integer(1))])),
+            required("results", array(Schema::Object(listing()))),
+        ]),
+        Method::Info => Schema::Object(vec![
+            required("titles", titles()),
+            required("altTitles", array(string())),
+            required("description", string()),
+            required("poster", Schema::Url),
+            optional("banner", nullable(Schema::Url)),
+            optional(
+                "status",
+                nullable(Schema::Enum(&[
+                    "Finished",
+                    "Ongoing",
+                    "Upcoming",
+                    "Hiatus",
+                    "Cancelled",
+                    "Unknown",
+                ])),
+            ),
+            optional("rating", nullable(Schema::Number)),
+            optional("yearReleased", nullable(integer(0))),
+            required("mediaType", non_empty()),
+            required(
+                "seasons",
+                array(Schema::Object(vec![
+                    required("name", non_empty()),
+                    required("url", non_empty()),
+                    optional("selected", Schema::Bool),
+                ])),
+            ),
+        ]),
+        Method::Media => array(Schema::Object(vec![
+            required("title", string()),
+            required(
+                "pagination",
+                array(Schema::Object(vec![
+                    required("id", string()),
+                    required("title", string()),
+                    required(
+                        "items",
+                        array(Schema::Object(vec![
+                            required("url", non_empty()),
+                            required("number", Schema::Number),
+                            optional("title", nullable(string())),
+                            optional("language", nullable(string())),
+                            optional("thumbnail", nullable(Schema::Url)),
+                            optional("description", nullable(string())),
+                            optional("indicator", nullable(string())),
+                        ])),
+                    ),
+                ])),
+            ),
+        ])),
+        Method::Servers => array(Schema::Object(vec![
+            required("title", string()),
+            required(
+                "list",
+                array(Schema::Object(vec![
+                    required("name", non_empty()),
+                    required("url", non_empty()),
+                ])),
+            ),
+        ])),
+        Method::Sources => Schema::Object(vec![
+            required(
+                "streams",
+                array(Schema::Object(vec![
+                    required("file", Schema::Url),
+                    required("type", Schema::Enum(&["hls", "mp4", "dash"])),
+                    required("quality", non_empty()),
+                ])),
+            ),
+            required(
+                "subtitles",
+                array(Schema::Object(vec![
+                    required("url", Schema::Url),
+                    required("language", non_empty()),
+                ])),
+            ),
+            required(
+                "skips",
+                array(Schema::Object(vec![
+                    required("start", Schema::Number),
+                    required("end", Schema::Number),
+                    required("type", non_empty()),
+                ])),
+            ),
+            optional("headers", Schema::Map(Box::new(string()))),
+        ]),
+    }
+}
+
+/// Checks the result of `method` against the module result schema.
+///
+/// The schema of `sources` describes streams, so it only applies to video
+/// modules and scripts of unknown type. What manga and novel modules return
+/// there is not checked.
+pub fn validate(method: Method, value: &Value, module_type: Option<ModuleType>) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let video = module_type.is_none_or(|module_type| module_type == ModuleType::Video);
+    if method == Method::Sources && !video {
+        return violations;
+    }
+    check(&result_schema(method), value, "$", &mut violations);
+    violations
+}
+
+/// The entries a result lists, with their paths: the titles of `discover`
+/// and `search`, the episodes or chapters of `media`, the servers of
+/// `servers` and the streams of `sources`. `info` lists the title itself.
+///
+/// Parts of the result that do not have the expected shape are skipped.
+pub fn items(method: Method, value: &Value) -> Vec<(String, &Value)> {
+    let mut items = Vec::new();
+
+    match method {
+        Method::Discover => {
+            for (section, value) in value.as_array().into_iter().flatten().enumerate() {
+                for (index, item) in value["data"].as_array().into_iter().flatten().enumerate() {
+                    items.push((format!("$[{}].data[{}]", section, index), item));
+                }
+            }
+        }
+        Method::Search => {
+            for (index, item) in value["results"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                items.push((format!("$.results[{}]", index), item));
+            }
+        }
+        Method::Info => {
+            if value.is_object() {
+                items.push(("$".to_string(), value));
+            }
+        }
+        Method::Media => {
+            for (group, value) in value.as_array().into_iter().flatten().enumerate() {
+                let pages = value["pagination"].as_array().into_iter().flatten();
+                for (page, value) in pages.enumerate() {
+                    let entries = value["items"].as_array().into_iter().flatten();
+                    for (index, item) in entries.enumerate() {
+                        let path = format!("$[{}].pagination[{}].items[{}]", group, page, index);
+                        items.push((path, item));
+                    }
+                }
+            }
+        }
+        Method::Servers => {
+            for (group, value) in value.as_array().into_iter().flatten().enumerate() {
+                for (index, item) in value["list"].as_array().into_iter().flatten().enumerate() {
+                    items.push((format!("$[{}].list[{}]", group, index), item));
+                }
+            }
+        }
+        Method::Sources => {
+            for (index, item) in value["streams"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                items.push((format!("$.streams[{}]", index), item));
+            }
+        }
+    }
+
+    items
+}
+
+fn check(schema: &Schema, value: &Value, path: &str, violations: &mut Vec<Violation>) {
+    let mut violation = |message: String| {
+        violations.push(Violation {
+            path: path.to_string(),
+            message,
+        })
+    };
+
+    match schema {
+        Schema::String { non_empty } => match value {
+            Value::String(string) if *non_empty && string.trim().is_empty() => {
+                violation("expected a non-empty string".to_string())
+            }
+            Value::String(_) => {}
+            _ => violation(format!("expected a string, got {}", type_name(value))),
+        },
+        Schema::Url => match value {
+            Value::String(string) => match url::Url::parse(string) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                Ok(url) => violation(format!(
+                    "expected an http(s) url, got a {} url",
+                    url.scheme()
+                )),
+                Err(err) => violation(format!("expected an absolute url ({}): {:?}", err, string)),
+            },
+            _ => violation(format!("expected a url string, got {}", type_name(value))),
+        },
+        Schema::Number => {
+            if !value.is_number() {
+                violation(format!("expected a number, got {}", type_name(value)));
+            }
+        }
+        Schema::Integer { min, max } => match value.as_f64() {
+            Some(number) if number.fract() != 0.0 => {
+                violation(format!("expected an integer, got {}", number))
+            }
+            Some(number) if number < *min as f64 || number > *max as f64 => {
+                if *max == i64::MAX {
+                    violation(format!(
+                        "expected an integer of at least {}, got {}",
+                        min, number
+                    ))
+                } else {
+                    violation(format!(
+                        "expected an integer between {} and {}, got {}",
+                        min, max, number
+                    ))
+                }
+            }
+            Some(_) => {}
+            None => violation(format!("expected an integer, got {}", type_name(value))),
+        },
+        Schema::Bool => {
+            if !value.is_boolean() {
+                violation(format!("expected a boolean, got {}", type_name(value)));
+            }
+        }
+        Schema::Enum(variants) => match value {
+            Value::String(string) if variants.contains(&string.as_str()) => {}
+            Value::String(string) => violation(format!(
+                "expected one of {}, got {:?}",
+                variants.join(", "),
+                string
+            )),
+            _ => violation(format!("expected a string, got {}", type_name(value))),
+        },
+        Schema::Array(items) => match value {
+            Value::Array(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    check(items, value, &format!("{}[{}]", path, index), violations);
+                }
+            }
+            _ => violation(format!("expected an array, got {}", type_name(value))),
+        },
+        Schema::Object(fields) => match value {
+            Value::Object(map) => {
+                for field in fields {
+                    let field_path = format!("{}.{}", path, field.name);
+                    match map.get(field.name) {
+                        Some(value) => check(&field.schema, value, &field_path, violations),
+                        None if field.required => violations.push(Violation {
+                            path: field_path,
+                            message: "required field is missing".to_string(),
+                        }),
+                        None => {}
+                    }
+                }
+            }
+            _ => violation(format!("expected an object, got {}", type_name(value))),
+        },
+        Schema::Map(values) => match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    check(values, value, &format!("{}.{}", path, key), violations);
+                }
+            }
+            _ => violation(format!("expected an object, got {}", type_name(value))),
+        },
+        Schema::Nullable(schema) => {
+            if !value.is_null() {
+                check(schema, value, path, violations);
+            }
+        }
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paths(method: Method, value: Value) -> Vec<String> {
+        validate(method, &value, None)
+            .into_iter()
+            .map(|violation| violation.path)
+            .collect()
+    }
+
+    fn listing(url: &str) -> Value {
+        json!({
+            "url": url,
+            "titles": { "primary": "Title", "secondary": null },
+            "poster": "https://example.com/poster.jpg",
+        })
+    }
+
+    #[test]
+    fn discover_checks_sections_and_listings() {
+        let mut item = listing("/title/1");
+        item["description"] = json!("");
+        let valid = json!([{ "title": "Popular", "type": 0, "data": [item] }]);
+        assert!(paths(Method::Discover, valid).is_empty());
+
+        let invalid = json!([{ "title": "", "type": 4, "data": [{ "url": "/title/1" }] }]);
+        assert_eq!(
+            paths(Method::Discover, invalid),
+            [
+                "$[0].title",
+                "$[0].type",
+                "$[0].data[0].titles",
+                "$[0].data[0].poster",
+                "$[0].data[0].description",
+            ]
+        );
+    }
+
+    #[test]
+    fn search_requires_at_least_one_page() {
+        let valid = json!({ "info": { "pages": 1 }, "results": [listing("/title/1")] });
+        assert!(paths(Method::Search, valid).is_empty());
+
+        let invalid = json!({ "info": { "pages": 0 }, "results": [listing("")] });
+        assert_eq!(
+            paths(Method::Search, invalid),
+            ["$.info.pages", "$.results[0].url"]
+        );
+    }
+
+    #[test]
+    fn info_checks_status_and_urls() {
+        let valid = json!({
+            "titles": { "primary": "Title" },
+            "altTitles": [],
+            "description": "",
+            "poster": "https://example.com/poster.jpg",
+            "banner": null,
+            "status": "Ongoing",
+            "mediaType": "Episodes",
+            "seasons": [{ "name": "Season 1", "url": "/season/1", "selected": true }],
+        });
+        assert!(paths(Method::Info, valid.clone()).is_empty());
+
+        let mut invalid = valid;
+        invalid["status"] = json!("Airing");
+        invalid["poster"] = json!("/poster.jpg");
+        invalid["banner"] = json!("ftp://example.com/banner.jpg");
+        invalid["yearReleased"] = json!(2020.5);
+        assert_eq!(
+            paths(Method::Info, invalid),
+            ["$.poster", "$.banner", "$.status", "$.yearReleased"]
+        );
+    }
+
+    #[test]
+    fn media_checks_items() {
+        let valid = json!([{
+            "title": "Episodes",
+            "pagination": [{
+                "id": "1",
+                "title": "1-12",
+                "items": [{ "url": "/episode/1", "number": 1, "thumbnail": null }],
+            }],
+        }]);
+        assert!(paths(Method::Media, valid).is_empty());
+
+        let invalid = json!([{
+            "title": "Episodes",
+            "pagination": [{ "id": "1", "title": "1-12", "items": [{ "url": "/episode/1", "number": "1" }] }],
+        }]);
+        assert_eq!(
+            paths(Method::Media, invalid),
+            ["$[0].pagination[0].items[0].number"]
+        );
+    }
+
+    #[test]
+    fn servers_require_names() {
+        let valid = json!([{ "title": "Sub", "list": [{ "name": "Main", "url": "/embed/1" }] }]);
+        assert!(paths(Method::Servers, valid).is_empty());
+
+        let invalid = json!([{ "title": "Sub", "list": [{ "name": " ", "url": "/embed/1" }] }]);
+        assert_eq!(paths(Method::Servers, invalid), ["$[0].list[0].name"]);
+    }
+
+    #[test]
+    fn sources_check_streams_subtitles_and_headers() {
+        let valid = json!({
+            "streams": [{ "file": "https://cdn.example.com/master.m3u8", "type": "hls", "quality": "auto" }],
+            "subtitles": [{ "url": "https://cdn.example.com/en.vtt", "language": "English" }],
+            "skips": [{ "start": 0, "end": 90.5, "type": "Opening" }],
+            "headers": { "Referer": "https://example.com/" },
+        });
+        assert!(paths(Method::Sources, valid).is_empty());
+
+        let invalid = json!({
+            "streams": [{ "file": "master.m3u8", "type": "webm", "quality": "auto" }],
+            "subtitles": [],
+            "skips": [{ "start": "0", "end": 90, "type": "Opening" }],
+            "headers": { "Referer": 1 },
+        });
+        assert_eq!(
+            paths(Method::Sources, invalid),
+            [
+                "$.streams[0].file",
+                "$.streams[0].type",
+                "$.skips[0].start",
+                "$.headers.Referer",
+            ]
+        );
+    }
+
+    #[test]
+    fn sources_are_only_checked_for_video_modules() {
+        let pages = json!(["https://example.com/1.jpg", "https://example.com/2.jpg"]);
+        for module_type in [ModuleType::Manga, ModuleType::Novel] {
+            assert!(validate(Method::Sources, &pages, Some(module_type)).is_empty());
+        }
+        for module_type in [None, Some(ModuleType::Video)] {
+            assert_eq!(
+                validate(Method::Sources, &pages, module_type)[0].to_string(),
+                "$: expected an object, got an array"
+            );
+        }
+
+        // Only `sources` depends on the type.
+        assert!(!validate(Method::Search, &json!({}), Some(ModuleType::Manga)).is_empty());
+    }
+
+    #[test]
+    fn describes_what_was_expected() {
+        let violations = validate(
+            Method::Search,
+            &json!({ "info": null, "results": {} }),
+            None,
+        );
+        let messages: Vec<_> = violations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "$.info: expected an object, got null",
+                "$.results: expected an array, got an object",
+            ]
+        );
+    }
+}
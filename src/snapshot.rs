@@ -0,0 +1,475 @@
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// What a redacted value is replaced with.
+const REDACTED: &str = "[redacted]";
+
+/// Longest rendering of a value shown in a diff before it is cut off.
+const MAX_VALUE_WIDTH: usize = 80;
+
+/// A redaction rule as written in the test file: either a path whose values
+/// are replaced entirely, or a path plus a regex whose matches are replaced
+/// inside string values.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RuleSpec {
+    Path(String),
+    Pattern {
+        path: String,
+        pattern: String,
+        replace: Option<String>,
+    },
+}
+
+/// A compiled redaction rule.
+pub struct Rule {
+    path: Vec<Segment>,
+    pattern: Option<(Regex, String)>,
+}
+
+/// One step of a JSON path.
+enum Segment {
+    /// `.name` or `['name']`
+    Key(String),
+    /// `[0]`
+    Index(usize),
+    /// `.*` or `[*]`
+    Wildcard,
+    /// `..name` or `..*`, which matches at any depth
+    Descendant(Option<String>),
+}
+
+impl Rule {
+    pub fn compile(spec: &RuleSpec) -> Result<Rule, String> {
+        let (path, pattern) = match spec {
+            RuleSpec::Path(path) => (path, None),
+            RuleSpec::Pattern {
+                path,
+                pattern,
+                replace,
+            } => {
+                let regex = Regex::new(pattern)
+                    .map_err(|err| format!("invalid pattern for {}: {}", path, err))?;
+                let replace = replace.clone().unwrap_or_else(|| REDACTED.to_string());
+                (path, Some((regex, replace)))
+            }
+        };
+        let path = parse_path(path).map_err(|err| format!("invalid path {:?}: {}", path, err))?;
+        Ok(Rule { path, pattern })
+    }
+
+    fn apply(&self, value: &mut Value) {
+        visit(value, &self.path, &mut |value| match &self.pattern {
+            None => *value = Value::String(REDACTED.to_string()),
+            Some((regex, replace)) => {
+                if let Value::String(string) = value {
+                    *string = regex.replace_all(string, replace.as_str()).into_owned();
+                }
+            }
+        });
+    }
+}
+
+/// Parses paths like `$.results[*].url`, `$..token` or `$['odd key'][0]`.
+fn parse_path(path: &str) -> Result<Vec<Segment>, String> {
+    let rest = path
+        .strip_prefix('$')
+        .ok_or_else(|| "paths start with `$`".to_string())?;
+    let chars: Vec<char> = rest.chars().collect();
+    let mut segments = Vec::new();
+    let mut index = 0;
+
+    let name = |index: &mut usize| {
+        let start = *index;
+        while *index < chars.len() && chars[*index] != '.' && chars[*index] != '[' {
+            *index += 1;
+        }
+        chars[start..*index].iter().collect::<String>()
+    };
+
+    while index < chars.len() {
+        match chars[index] {
+            '.' if chars.get(index + 1) == Some(&'.') => {
+                index += 2;
+                let name = name(&mut index);
+                match name.as_str() {
+                    "" => return Err("expected a name after `..`".to_string()),
+                    "*" => segments.push(Segment::Descendant(None)),
+                    _ => segments.push(Segment::Descendant(Some(name))),
+                }
+            }
+            '.' => {
+                index += 1;
+                let name = name(&mut index);
+                match name.as_str() {
+                    "" => return Err("expected a name after `.`".to_string()),
+                    "*" => segments.push(Segment::Wildcard),
+                    _ => segments.push(Segment::Key(name)),
+                }
+            }
+            '[' => {
+                let end = chars[index..]
+                    .iter()
+                    .position(|&c| c == ']')
+                    .ok_or_else(|| "unclosed `[`".to_string())?;
+                let inner: String = chars[index + 1..index + end].iter().collect();
+                index += end + 1;
+
+                let inner = inner.trim();
+                if inner == "*" {
+                    segments.push(Segment::Wildcard);
+                } else if let Ok(number) = inner.parse() {
+                    segments.push(Segment::Index(number));
+                } else if inner.len() >= 2
+                    && (inner.starts_with('\'') && inner.ends_with('\'')
+                        || inner.starts_with('"') && inner.ends_with('"'))
+                {
+                    segments.push(Segment::Key(inner[1..inner.len() - 1].to_string()));
+                } else {
+                    return Err(format!("unexpected `[{}]`", inner));
+                }
+            }
+            c => return Err(format!("unexpected {:?}", c)),
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Calls `f` on every value `path` selects.
+fn visit(value: &mut Value, path: &[Segment], f: &mut dyn FnMut(&mut Value)) {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return f(value),
+    };
+
+    match (segment, value) {
+        (Segment::Key(key), Value::Object(map)) => {
+            if let Some(value) = map.get_mut(key) {
+                visit(value, rest, f);
+            }
+        }
+        (Segment::Index(index), Value::Array(values)) => {
+            if let Some(value) = values.get_mut(*index) {
+                visit(value, rest, f);
+            }
+        }
+        (Segment::Wildcard, Value::Object(map)) => {
+            for value in map.values_mut() {
+                visit(value, rest, f);
+            }
+        }
+        (Segment::Wildcard, Value::Array(values)) => {
+            for value in values {
+                visit(value, rest, f);
+            }
+        }
+        (Segment::Descendant(name), value) => {
+            // Look for the name here, then keep descending with the same
+            // segment so matches at every depth are found.
+            match value {
+                Value::Object(map) => {
+                    for (key, value) in map.iter_mut() {
+                        if name.as_ref().is_none_or(|name| name == key) {
+                            visit(value, rest, f);
+                        }
+                        visit(value, path, f);
+                    }
+                }
+                Value::Array(values) => {
+                    for value in values {
+                        if name.is_none() {
+                            visit(value, rest, f);
+                        }
+                        visit(value, path, f);
+                    }
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Brings a result into the form stored in snapshots: redacted and with
+/// object keys sorted, so key order changes in the module do not count as
+/// differences.
+pub fn normalise(value: &Value, rules: &[&Rule]) -> Value {
+    let mut value = value.clone();
+    for rule in rules {
+        rule.apply(&mut value);
+    }
+    sort_keys(&mut value);
+    value
+}
+
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.sort_keys();
+            for value in map.values_mut() {
+                sort_keys(value);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                sort_keys(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A difference between the snapshot and the current result.
+pub enum Change {
+    Added(String, Value),
+    Removed(String, Value),
+    Changed(String, Value, Value),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added(path, value) => write!(f, "+ {}: {}", path, render(value)),
+            Change::Removed(path, value) => write!(f, "- {}: {}", path, render(value)),
+            Change::Changed(path, old, new) => {
+                write!(f, "~ {}: {} -> {}", path, render(old), render(new))
+            }
+        }
+    }
+}
+
+fn render(value: &Value) -> String {
+    let json = value.to_string();
+    if json.chars().count() <= MAX_VALUE_WIDTH {
+        return json;
+    }
+    let cut: String = json.chars().take(MAX_VALUE_WIDTH - 3).collect();
+    format!("{}...", cut)
+}
+
+/// Lists the differences between two values by path. Arrays are compared
+/// element by element.
+pub fn diff(expected: &Value, actual: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_at("$", expected, actual, &mut changes);
+    changes
+}
+
+fn diff_at(path: &str, expected: &Value, actual: &Value, changes: &mut Vec<Change>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected) in expected {
+                let path = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(actual) => diff_at(&path, expected, actual, changes),
+                    None => changes.push(Change::Removed(path, expected.clone())),
+                }
+            }
+            for (key, actual) in actual {
+                if !expected.contains_key(key) {
+                    changes.push(Change::Added(format!("{}.{}", path, key), actual.clone()));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for index in 0..expected.len().max(actual.len()) {
+                let path = format!("{}[{}]", path, index);
+                match (expected.get(index), actual.get(index)) {
+                    (Some(expected), Some(actual)) => diff_at(&path, expected, actual, changes),
+                    (Some(expected), None) => changes.push(Change::Removed(path, expected.clone())),
+                    (None, Some(actual)) => changes.push(Change::Added(path, actual.clone())),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        _ if expected != actual => changes.push(Change::Changed(
+            path.to_string(),
+            expected.clone(),
+            actual.clone(),
+        )),
+        _ => {}
+    }
+}
+
+/// What comparing a result with its snapshot found.
+pub enum Comparison {
+    Matched,
+    /// The snapshot was written because `update` was set.
+    Written,
+    Changed(Vec<Change>),
+    /// There is no snapshot yet and `update` was not set.
+    Missing,
+}
+
+/// Compares a normalised result with the snapshot at `path`. With `update`
+/// set the snapshot is written instead, whether it exists or not.
+pub fn compare(path: &Path, value: &Value, update: bool) -> Result<Comparison, String> {
+    let existing = if update {
+        None
+    } else {
+        match fs::read_to_string(path) {
+            Ok(content) => Some(
+                serde_json::from_str::<Value>(&content)
+                    .map_err(|err| format!("{} is not valid JSON: {}", path.display(), err))?,
+            ),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Comparison::Missing),
+            Err(err) => return Err(format!("Could not read {}: {}", path.display(), err)),
+        }
+    };
+
+    match existing {
+        Some(expected) if &expected == value => Ok(Comparison::Matched),
+        Some(expected) => Ok(Comparison::Changed(diff(&expected, value))),
+        None => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|err| format!("Could not create {}: {}", parent.display(), err))?;
+            }
+            let json = serde_json::to_string_pretty(value).unwrap() + "\n";
+            fs::write(path, json)
+                .map_err(|err| format!("Could not write {}: {}", path.display(), err))?;
+            Ok(Comparison::Written)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(spec: Value) -> Rule {
+        Rule::compile(&serde_json::from_value(spec).unwrap()).unwrap()
+    }
+
+    fn redact(value: Value, specs: Value) -> Value {
+        let rules: Vec<Rule> = specs
+            .as_array()
+            .unwrap()
+            .iter()
+            .cloned()
+            .map(rule)
+            .collect();
+        let rules: Vec<&Rule> = rules.iter().collect();
+        normalise(&value, &rules)
+    }
+
+    #[test]
+    fn parses_paths() {
+        let segments = parse_path("$.results[*].url").unwrap();
+        assert!(matches!(
+            segments.as_slice(),
+            [Segment::Key(results), Segment::Wildcard, Segment::Key(url)]
+                if results == "results" && url == "url"
+        ));
+
+        let segments = parse_path("$..token['odd key'][2].*").unwrap();
+        assert!(matches!(
+            segments.as_slice(),
+            [
+                Segment::Descendant(Some(token)),
+                Segment::Key(odd),
+                Segment::Index(2),
+                Segment::Wildcard,
+            ] if token == "token" && odd == "odd key"
+        ));
+
+        assert!(matches!(
+            parse_path("$..*").unwrap().as_slice(),
+            [Segment::Descendant(None)]
+        ));
+        assert!(parse_path("$").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        for path in ["results", "$.", "$..", "$[0", "$[abc]", "$ .x"] {
+            assert!(parse_path(path).is_err(), "{} should not parse", path);
+        }
+
+        let spec = serde_json::from_value(json!({ "path": "$.url", "pattern": "(" })).unwrap();
+        assert!(Rule::compile(&spec).is_err());
+    }
+
+    #[test]
+    fn redacts_whole_values() {
+        let value = json!({
+            "results": [{ "url": "/a", "title": "A" }, { "url": "/b", "title": "B" }],
+            "meta": { "timestamp": 1, "nested": [{ "timestamp": 2 }] },
+        });
+        let redacted = redact(value, json!(["$.results[*].url", "$..timestamp"]));
+        assert_eq!(
+            redacted,
+            json!({
+                "meta": { "nested": [{ "timestamp": "[redacted]" }], "timestamp": "[redacted]" },
+                "results": [
+                    { "title": "A", "url": "[redacted]" },
+                    { "title": "B", "url": "[redacted]" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_pattern_matches_inside_strings() {
+        let value = json!({ "streams": [{ "file": "https://cdn/a.m3u8?token=abc&x=1" }] });
+        let redacted = redact(
+            value.clone(),
+            json!([{ "path": "$.streams[*].file", "pattern": "token=[^&]+" }]),
+        );
+        assert_eq!(
+            redacted["streams"][0]["file"],
+            "https://cdn/a.m3u8?[redacted]&x=1"
+        );
+
+        let redacted = redact(
+            value,
+            json!([{ "path": "$..file", "pattern": "token=[^&]+", "replace": "token=T" }]),
+        );
+        assert_eq!(
+            redacted["streams"][0]["file"],
+            "https://cdn/a.m3u8?token=T&x=1"
+        );
+    }
+
+    #[test]
+    fn normalise_sorts_keys() {
+        let normalised = normalise(&json!({ "b": 1, "a": { "d": 2, "c": 3 } }), &[]);
+        assert_eq!(normalised.to_string(), r#"{"a":{"c":3,"d":2},"b":1}"#);
+    }
+
+    #[test]
+    fn diffs_by_path() {
+        let expected = json!({ "title": "A", "tags": ["x", "y"], "gone": true });
+        let actual = json!({ "title": "B", "tags": ["x"], "new": null });
+        let changes: Vec<_> = diff(&expected, &actual)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                r#"~ $.title: "A" -> "B""#,
+                r#"- $.tags[1]: "y""#,
+                "- $.gone: true",
+                "+ $.new: null",
+            ]
+        );
+        assert!(diff(&expected, &expected).is_empty());
+    }
+
+    #[test]
+    fn cuts_long_values_in_diffs() {
+        let long = "x".repeat(200);
+        let change = Change::Added("$.text".to_string(), json!(long));
+        let line = change.to_string();
+        assert!(line.ends_with("..."));
+        assert_eq!(line.chars().count(), "+ $.text: ".len() + MAX_VALUE_WIDTH);
+    }
+}
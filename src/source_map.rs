@@ -0,0 +1,171 @@
+use crate::console;
+use base64::Engine;
+use regex::{Captures, Regex};
+use std::sync::Arc;
+
+/// The comment bundlers end a script with to point at its source map.
+const URL_COMMENT: &str = "//# sourceMappingURL=";
+
+/// Maps positions in a bundled script back to the sources it was built from.
+pub struct SourceMap {
+    /// The name the bundled script was compiled under.
+    script: String,
+    map: sourcemap::SourceMap,
+    /// `script:line:column` as it appears in stack traces.
+    position: Regex,
+}
+
+/// A position in one of the original sources. Lines and columns are
+/// 1-based.
+pub struct Position {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    /// The source line, when the map embeds the source.
+    pub source_line: Option<String>,
+}
+
+impl SourceMap {
+    /// Finds the source map of `source`: inline as a data URL, at the path
+    /// its `sourceMappingURL` comment names, or next to the script as
+    /// `<script>.map`. `read` loads a file relative to the script's
+    /// directory.
+    ///
+    /// A map that cannot be read is reported as a warning and ignored, so
+    /// the script still runs with bundle positions.
+    pub fn find(
+        script: &str,
+        file_name: &str,
+        source: &str,
+        read: impl Fn(&str) -> Option<Vec<u8>>,
+    ) -> Option<SourceMap> {
+        let url = source
+            .lines()
+            .rev()
+            .map(str::trim)
+            .find(|line| line.starts_with(URL_COMMENT))
+            .map(|line| line[URL_COMMENT.len()..].trim());
+
+        let (location, json) = match url {
+            Some(url) if url.starts_with("data:") => ("inline source map".to_string(), decode(url)),
+            Some(url) => (
+                url.to_string(),
+                read(url).ok_or_else(|| "could not be read".to_string()),
+            ),
+            None => {
+                let adjacent = format!("{}.map", file_name);
+                let json = read(&adjacent)?;
+                (adjacent, Ok(json))
+            }
+        };
+
+        match json.and_then(|json| SourceMap::parse(script, &json)) {
+            Ok(map) => Some(map),
+            Err(err) => {
+                let message = format!("warning: {}: {}: {}", script, location, err);
+                console::log(console::Level::Warn, &message);
+                None
+            }
+        }
+    }
+
+    fn parse(script: &str, json: &[u8]) -> Result<SourceMap, String> {
+        let map = sourcemap::SourceMap::from_slice(json).map_err(|err| err.to_string())?;
+        let position = Regex::new(&format!(r"{}:(\d+):(\d+)", regex::escape(script))).unwrap();
+        Ok(SourceMap {
+            script: script.to_string(),
+            map,
+            position,
+        })
+    }
+
+    /// Makes exceptions and console messages from the isolate use the
+    /// original sources. Every script and imported module installs its own.
+    pub fn install(self: &Arc<Self>, isolate: &mut v8::Isolate) {
+        match isolate.get_slot_mut::<SourceMaps>() {
+            Some(maps) => maps.0.push(self.clone()),
+            None => {
+                isolate.set_slot(SourceMaps(vec![self.clone()]));
+            }
+        }
+    }
+
+    /// Looks up the original position of `line` and `column` in `file`, which
+    /// only maps when it is the bundled script.
+    pub fn lookup(&self, file: &str, line: usize, column: usize) -> Option<Position> {
+        if file != self.script || line == 0 || column == 0 {
+            return None;
+        }
+        let token = self.map.lookup_token(line as u32 - 1, column as u32 - 1)?;
+        let file = token.get_source()?.to_string();
+        let line = token.get_src_line() as usize;
+        let source_line = self
+            .map
+            .get_source_contents(token.get_src_id())
+            .and_then(|contents| contents.lines().nth(line))
+            .map(str::to_string);
+        Some(Position {
+            file,
+            line: line + 1,
+            column: token.get_src_col() as usize + 1,
+            source_line,
+        })
+    }
+
+    /// Rewrites every bundle position in a stack trace or message to the
+    /// original source.
+    pub fn remap(&self, text: &str) -> String {
+        self.position
+            .replace_all(text, |captures: &Captures| {
+                let line = captures[1].parse().unwrap_or(0);
+                let column = captures[2].parse().unwrap_or(0);
+                match self.lookup(&self.script, line, column) {
+                    Some(position) => {
+                        format!("{}:{}:{}", position.file, position.line, position.column)
+                    }
+                    None => captures[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// The source maps installed on an isolate, one per compiled file.
+#[derive(Clone)]
+pub struct SourceMaps(Vec<Arc<SourceMap>>);
+
+impl SourceMaps {
+    /// Returns the source maps installed on the isolate behind `scope`, if
+    /// any.
+    pub fn get(scope: &mut v8::HandleScope) -> Option<SourceMaps> {
+        scope.get_slot::<SourceMaps>().cloned()
+    }
+
+    /// Looks up the original position of `line` and `column` in `file`, using
+    /// the map of that file.
+    pub fn lookup(&self, file: &str, line: usize, column: usize) -> Option<Position> {
+        self.0.iter().find_map(|map| map.lookup(file, line, column))
+    }
+
+    /// Rewrites the positions of every mapped file in a stack trace or
+    /// message to the original sources.
+    pub fn remap(&self, text: &str) -> String {
+        self.0
+            .iter()
+            .fold(text.to_string(), |text, map| map.remap(&text))
+    }
+}
+
+/// Decodes a `data:application/json;base64,...` URL.
+fn decode(url: &str) -> Result<Vec<u8>, String> {
+    let (header, data) = url
+        .split_once(',')
+        .ok_or_else(|| "malformed data URL".to_string())?;
+    if header.ends_with(";base64") {
+        base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|err| format!("invalid base64: {}", err))
+    } else {
+        Ok(data.as_bytes().to_vec())
+    }
+}
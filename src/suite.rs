@@ -0,0 +1,366 @@
+use crate::archive;
+use crate::cassette::{Cassette, Mode};
+use crate::cli::Method;
+use crate::manifest::ModuleType;
+use crate::runtime::{self, Script};
+use crate::scaffold;
+use crate::schema;
+use crate::snapshot::{self, Comparison, Rule, RuleSpec};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Location of the test file inside a module directory.
+pub const TEST_FILE: &str = "tests/module.toml";
+
+/// Directory next to the test file that snapshots are stored in.
+const SNAPSHOT_DIR: &str = "__snapshots__";
+
+/// Seconds a case may take unless it sets its own `timeout`.
+const DEFAULT_TIMEOUT: u64 = 60;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestFile {
+    #[serde(default)]
+    snapshots: Snapshots,
+    #[serde(default, rename = "case")]
+    cases: Vec<Case>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Snapshots {
+    /// Redaction rules applied to the result of every case.
+    #[serde(default)]
+    redact: Vec<RuleSpec>,
+}
+
+/// One call of an entry point and what its result has to look like.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: String,
+    entry: Method,
+    /// The first argument, e.g. the query or url.
+    arg: Option<Value>,
+    /// Arguments passed after `arg`.
+    #[serde(default)]
+    args: Vec<Value>,
+    /// Directory of recorded requests, relative to the test file.
+    cassette: Option<PathBuf>,
+    timeout: Option<u64>,
+    #[serde(default)]
+    expect: Expect,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expect {
+    /// Fewest entries the result has to list, see `schema::items`.
+    min_results: Option<usize>,
+    /// Fields of every entry that must not be missing or empty.
+    #[serde(default)]
+    non_empty: Vec<String>,
+    /// Fields of every entry that must be strings matching a regex.
+    #[serde(default)]
+    matches: BTreeMap<String, String>,
+    /// Compare the whole result with the stored snapshot.
+    #[serde(default)]
+    snapshot: bool,
+    /// Redaction rules applied to this case's result on top of the ones
+    /// in `[snapshots]`.
+    #[serde(default)]
+    redact: Vec<RuleSpec>,
+    /// Check the result against the module result schema. On by default.
+    valid: Option<bool>,
+}
+
+/// What to run and how.
+pub struct Options {
+    /// Only run cases whose name contains this.
+    pub filter: Option<String>,
+    /// Refresh the cassettes from the network instead of replaying them.
+    pub record: bool,
+    /// Overwrite the stored snapshots with the current results.
+    pub update_snapshots: bool,
+}
+
+/// How many cases passed and failed.
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Runs every case of the test file against the module in `dir`, each in a
+/// fresh isolate, and prints the outcome of every case as it finishes.
+pub fn run(dir: &Path, file: Option<&Path>, options: &Options) -> Result<Summary, String> {
+    let file = match file {
+        Some(file) => file.to_path_buf(),
+        None => dir.join(TEST_FILE),
+    };
+    let content = fs::read_to_string(&file)
+        .map_err(|err| format!("Could not read {}: {}", file.display(), err))?;
+    let test_file: TestFile =
+        toml::from_str(&content).map_err(|err| format!("{}: {}", file.display(), err))?;
+    let base = file.parent().unwrap_or(Path::new("."));
+    let rules = compile_rules(&test_file.snapshots.redact)
+        .map_err(|err| format!("{}: {}", file.display(), err))?;
+
+    let script = archive::load_script(dir)?;
+
+    let cases: Vec<_> = test_file
+        .cases
+        .iter()
+        .filter(|case| {
+            options
+                .filter
+                .as_ref()
+                .is_none_or(|filter| case.name.contains(filter.as_str()))
+        })
+        .collect();
+    println!("running {} cases from {}", cases.len(), file.display());
+
+    // Cleared once up front rather than per case, since cases may share a
+    // cassette.
+    if options.record {
+        let dirs: BTreeSet<_> = cases
+            .iter()
+            .filter_map(|case| case.cassette.as_ref())
+            .collect();
+        for dir in dirs {
+            Cassette::clear(&base.join(dir))?;
+        }
+    }
+
+    let mut summary = Summary {
+        passed: 0,
+        failed: 0,
+    };
+    for case in cases {
+        let started = Instant::now();
+        let outcome = run_case(case, &script, base, &rules, options);
+        let elapsed = started.elapsed().as_secs_f64();
+
+        if outcome.failures.is_empty() {
+            summary.passed += 1;
+            println!("PASS  {} ({:.2}s)", case.name, elapsed);
+        } else {
+            summary.failed += 1;
+            println!("FAIL  {} ({:.2}s)", case.name, elapsed);
+        }
+        for message in outcome.notes.iter().chain(&outcome.failures) {
+            for line in message.lines() {
+                println!("      {}", line);
+            }
+        }
+    }
+
+    println!();
+    println!("{} passed, {} failed", summary.passed, summary.failed);
+    Ok(summary)
+}
+
+/// What happened in one case.
+struct Outcome {
+    /// Everything that did not meet the expectations of the case.
+    failures: Vec<String>,
+    /// Things worth knowing that are not failures, e.g. a new snapshot.
+    notes: Vec<String>,
+}
+
+impl Outcome {
+    fn failed(failure: String) -> Outcome {
+        Outcome {
+            failures: vec![failure],
+            notes: Vec::new(),
+        }
+    }
+}
+
+fn run_case(
+    case: &Case,
+    script: &Script,
+    base: &Path,
+    rules: &[Rule],
+    options: &Options,
+) -> Outcome {
+    let cassette = match &case.cassette {
+        Some(dir) => {
+            let mode = if options.record {
+                Mode::Record
+            } else {
+                Mode::Replay
+            };
+            match Cassette::new(&base.join(dir), mode) {
+                Ok(cassette) => Some(Arc::new(cassette)),
+                Err(err) => return Outcome::failed(err),
+            }
+        }
+        None => None,
+    };
+
+    let args: Vec<_> = case.arg.iter().chain(&case.args).cloned().collect();
+    let timeout = Duration::from_secs(case.timeout.unwrap_or(DEFAULT_TIMEOUT));
+
+    let json = match runtime::invoke(script, case.entry, &args, timeout, cassette.as_ref()) {
+        Ok(json) => json,
+        Err(err) => return Outcome::failed(err.to_string()),
+    };
+    let value = match json.as_deref().map(serde_json::from_str).transpose() {
+        Ok(value) => value.unwrap_or(Value::Null),
+        Err(err) => {
+            return Outcome::failed(format!("result could not be read back as JSON: {}", err))
+        }
+    };
+
+    let mut outcome = Outcome {
+        failures: check(case.entry, script.module_type, &case.expect, &value),
+        notes: Vec::new(),
+    };
+    if case.expect.snapshot {
+        let path = base.join(SNAPSHOT_DIR).join(snapshot_file(&case.name));
+        let case_rules = match compile_rules(&case.expect.redact) {
+            Ok(case_rules) => case_rules,
+            Err(err) => {
+                outcome.failures.push(err);
+                return outcome;
+            }
+        };
+        let rules: Vec<_> = rules.iter().chain(&case_rules).collect();
+        let normalised = snapshot::normalise(&value, &rules);
+
+        match snapshot::compare(&path, &normalised, options.update_snapshots) {
+            Ok(Comparison::Matched) => {}
+            Ok(Comparison::Written) => outcome
+                .notes
+                .push(format!("wrote snapshot {}", path.display())),
+            Ok(Comparison::Missing) => outcome.failures.push(format!(
+                "no snapshot at {}, run with --update-snapshots to write it",
+                path.display()
+            )),
+            Ok(Comparison::Changed(changes)) => {
+                let mut failure = format!("result does not match {}", path.display());
+                for change in &changes {
+                    failure.push_str(&format!("\n  {}", change));
+                }
+                outcome.failures.push(failure);
+            }
+            Err(err) => outcome.failures.push(err),
+        }
+    }
+    outcome
+}
+
+/// Names the snapshot of a case: readable from the slug of its name, and
+/// unique from a hash of the name, since names that differ only in
+/// punctuation or non-ASCII letters share a slug.
+fn snapshot_file(name: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(name));
+    match scaffold::slug(name).as_str() {
+        "" => format!("{}.json", &hash[..8]),
+        slug => format!("{}-{}.json", slug, &hash[..8]),
+    }
+}
+
+fn check(
+    entry: Method,
+    module_type: Option<ModuleType>,
+    expect: &Expect,
+    value: &Value,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    if expect.valid.unwrap_or(true) {
+        for violation in schema::validate(entry, value, module_type) {
+            failures.push(violation.to_string());
+        }
+    }
+
+    let items = schema::items(entry, value);
+    if let Some(min_results) = expect.min_results {
+        if items.len() < min_results {
+            failures.push(format!(
+                "expected at least {} results, got {}",
+                min_results,
+                items.len()
+            ));
+        }
+    }
+
+    for (path, item) in &items {
+        for field in &expect.non_empty {
+            let empty = match lookup(item, field) {
+                None | Some(Value::Null) => true,
+                Some(Value::String(string)) => string.trim().is_empty(),
+                Some(Value::Array(values)) => values.is_empty(),
+                Some(Value::Object(map)) => map.is_empty(),
+                Some(_) => false,
+            };
+            if empty {
+                failures.push(format!("{}.{}: expected a non-empty value", path, field));
+            }
+        }
+    }
+
+    for (field, pattern) in &expect.matches {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(err) => {
+                failures.push(format!("invalid regex for {}: {}", field, err));
+                continue;
+            }
+        };
+        for (path, item) in &items {
+            match lookup(item, field) {
+                Some(Value::String(string)) if regex.is_match(string) => {}
+                Some(Value::String(string)) => failures.push(format!(
+                    "{}.{}: expected a match for {:?}, got {:?}",
+                    path, field, pattern, string
+                )),
+                _ => failures.push(format!(
+                    "{}.{}: expected a string matching {:?}",
+                    path, field, pattern
+                )),
+            }
+        }
+    }
+
+    failures
+}
+
+/// Follows a path like `titles.primary` or `list.0.url` into a value.
+fn lookup<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(values) => values.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+fn compile_rules(specs: &[RuleSpec]) -> Result<Vec<Rule>, String> {
+    specs.iter().map(Rule::compile).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_files_are_unique_per_case() {
+        let names = ["search: one piece", "search, one piece", "検索", "探す", ""];
+        let files: BTreeSet<_> = names.iter().map(|name| snapshot_file(name)).collect();
+        assert_eq!(files.len(), names.len());
+
+        let file = snapshot_file("search: one piece");
+        assert!(file.starts_with("search-one-piece-"));
+        assert!(file.ends_with(".json"));
+        assert_eq!(snapshot_file("検索").len(), "12345678.json".len());
+    }
+}
@@ -0,0 +1,70 @@
+// {{name}}
+//
+// `chouten run <entry point> code.js ...` evaluates this file and calls the
+// methods of `new source.default()`, the same way the app does. Every method
+// may be async and should resolve with the shapes documented below.
+var source = (() => {
+  const BASE_URL = {{base_url}};
+
+  class Module {
+    // Sections shown on the discover page:
+    // [{ title, type, data: [{ url, titles: { primary, secondary }, poster, description }] }]
+    async discover() {
+      return [];
+    }
+
+    // { info: { pages }, results: [{ url, titles: { primary, secondary }, poster }] }
+    //
+    // Returns a placeholder so the generated tests pass. Scrape the site
+    // instead, e.g.:
+    //
+    //   const response = await fetch(`${BASE_URL}/search?q=${encodeURIComponent(query)}&page=${page}`);
+    //   const $ = cheerio.load(await response.text());
+    async search(query, page = 1) {
+      return {
+        info: { pages: 1 },
+        results: [
+          {
+            url: `${BASE_URL}/title/${encodeURIComponent(query)}`,
+            titles: { primary: query, secondary: null },
+            poster: `${BASE_URL}/poster.jpg`,
+          },
+        ],
+      };
+    }
+
+    // Details of the title behind a url returned by `discover` or `search`.
+    async info(url) {
+      return {
+        titles: { primary: "Untitled", secondary: null },
+        altTitles: [],
+        description: "",
+        poster: `${BASE_URL}/poster.jpg`,
+        banner: null,
+        status: "Unknown",
+        rating: null,
+        yearReleased: null,
+        mediaType: {{media_type}},
+        seasons: [],
+      };
+    }
+
+    // {{media_description}}:
+    // [{ title, pagination: [{ id, title, items: [{ url, number, title }] }] }]
+    async media(url) {
+      return [];
+    }
+
+    // [{ title, list: [{ name, url }] }]
+    async servers(url) {
+      return [];
+    }
+
+    // {{sources_description}}
+    async sources(url) {
+      return {{sources}};
+    }
+  }
+
+  return { default: Module };
+})();
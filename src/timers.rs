@@ -0,0 +1,105 @@
+use crate::console::{self, Level};
+use crate::event_loop::EventLoop;
+use crate::runtime::{set_function, Exception};
+use std::time::Duration;
+
+/// The host functions `js/timers.js` builds `setTimeout`, `setInterval`,
+/// their `clear` counterparts, `queueMicrotask`, `reportError` and the
+/// clocks of `Date` and `performance` on, as the object `__timers`.
+///
+/// `fakeTime` tells whether the event loop runs on a virtual clock, which
+/// `Date` then has to follow.
+pub fn host_object<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Object> {
+    let object = v8::Object::new(scope);
+    set_function(scope, object, "schedule", schedule);
+    set_function(scope, object, "cancel", cancel);
+    set_function(scope, object, "enqueueMicrotask", enqueue_microtask);
+    set_function(scope, object, "report", report);
+    set_function(scope, object, "now", now);
+
+    let fake_time = EventLoop::get(scope).borrow().fake_time();
+    let key = v8::String::new(scope, "fakeTime").unwrap();
+    let value = v8::Boolean::new(scope, fake_time);
+    object.set(scope, key.into(), value.into());
+    object
+}
+
+/// `schedule(callback, delay)`: calls `callback` once, on the turn of the
+/// event loop after `delay` milliseconds have passed, and returns the id of
+/// the timer.
+fn schedule(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let callback = match v8::Local::<v8::Function>::try_from(args.get(0)) {
+        Ok(callback) => v8::Global::new(scope, callback),
+        Err(_) => {
+            let message = v8::String::new(scope, "The callback must be a function.").unwrap();
+            let exception = v8::Exception::type_error(scope, message);
+            scope.throw_exception(exception);
+            return;
+        }
+    };
+    // Converted like a WebIDL `long`, the way browsers treat the delay.
+    let delay = args.get(1).int32_value(scope).unwrap_or(0).max(0);
+
+    let id = EventLoop::get(scope).borrow_mut().set_timer(
+        Duration::from_millis(delay as u64),
+        Box::new(move |scope: &mut v8::HandleScope| {
+            let try_catch = &mut v8::TryCatch::new(scope);
+            let callback = v8::Local::new(try_catch, callback);
+            let receiver = v8::undefined(try_catch);
+            if callback.call(try_catch, receiver.into(), &[]).is_none() {
+                let exception = Exception::caught(try_catch);
+                log_uncaught(&exception);
+            }
+        }),
+    );
+    rv.set_double(id as f64);
+}
+
+/// `cancel(id)`: stops a timer from firing. Unknown ids are ignored.
+fn cancel(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, _: v8::ReturnValue) {
+    if let Some(id) = args.get(0).number_value(scope) {
+        if id >= 1.0 && id.fract() == 0.0 {
+            EventLoop::get(scope).borrow_mut().clear_timer(id as u64);
+        }
+    }
+}
+
+/// `enqueueMicrotask(callback)`: runs `callback` at the next microtask
+/// checkpoint, with the promise reactions.
+fn enqueue_microtask(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    _: v8::ReturnValue,
+) {
+    match v8::Local::<v8::Function>::try_from(args.get(0)) {
+        Ok(callback) => scope.enqueue_microtask(callback),
+        Err(_) => {
+            let message = v8::String::new(scope, "The callback must be a function.").unwrap();
+            let exception = v8::Exception::type_error(scope, message);
+            scope.throw_exception(exception);
+        }
+    }
+}
+
+/// `now()`: the milliseconds since the event loop was created, on its clock.
+/// Timers fire on the same clock, so with fake time a timer of ten seconds
+/// moves it by ten seconds.
+fn now(scope: &mut v8::HandleScope, _: v8::FunctionCallbackArguments, mut rv: v8::ReturnValue) {
+    let elapsed = EventLoop::get(scope).borrow().elapsed();
+    rv.set_double(elapsed.as_secs_f64() * 1000.0);
+}
+
+/// `report(error)`: logs an exception nothing caught, such as one thrown by
+/// a timer callback. The script keeps running.
+fn report(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, _: v8::ReturnValue) {
+    let exception = Exception::new(scope, args.get(0));
+    log_uncaught(&exception);
+}
+
+fn log_uncaught(exception: &Exception) {
+    console::log(Level::Error, &format!("Uncaught exception: {}", exception));
+}
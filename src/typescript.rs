@@ -0,0 +1,162 @@
+use crate::console;
+use deno_ast::swc::ast::{ModuleDecl, ModuleItem};
+use deno_ast::{
+    EmitOptions, MediaType, ModuleSpecifier, ParseParams, SourceRangedForSpanned, SourceTextInfo,
+};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Part of the cache key, so output from other transpiler versions is not
+/// reused.
+const CACHE_VERSION: &str = "deno_ast-0.34";
+
+/// Whether a file needs transpiling before V8 can run it.
+pub fn is_typescript(name: &str) -> bool {
+    name.ends_with(".ts") || name.ends_with(".mts")
+}
+
+/// Strips the types from a TypeScript file and returns JavaScript with an
+/// inline source map pointing back at it.
+///
+/// The output is cached by the hash of the name and content under
+/// `$XDG_CACHE_HOME/chouten/typescript`, or `~/.cache/chouten/typescript`.
+pub fn transpile(name: &str, source: &str) -> Result<String, String> {
+    let cached = cache_file(name, source);
+    if let Some(output) = cached
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+    {
+        return Ok(output);
+    }
+
+    let output = emit(name, source)?;
+
+    if let Some(path) = cached {
+        let written =
+            fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(&path, &output));
+        if let Err(err) = written {
+            let message = format!("Could not cache {}: {}", path.display(), err);
+            console::log(console::Level::Debug, &message);
+        }
+    }
+    Ok(output)
+}
+
+/// Lists the specifiers of a JavaScript module's static imports and
+/// re-exports, along with the byte range of each string literal, quotes
+/// included.
+pub fn import_specifiers(name: &str, source: &str) -> Result<Vec<(String, Range<usize>)>, String> {
+    let media_type = if name.ends_with(".mjs") {
+        MediaType::Mjs
+    } else {
+        MediaType::JavaScript
+    };
+    let parsed = parse(name, source, media_type)?;
+    let start = parsed.text_info().range().start;
+
+    let mut specifiers = Vec::new();
+    for item in &parsed.module().body {
+        let src = match item {
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => &import.src,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportAll(export)) => &export.src,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)) => match &export.src {
+                Some(src) => src,
+                None => continue,
+            },
+            _ => continue,
+        };
+        specifiers.push((src.value.to_string(), src.range().as_byte_range(start)));
+    }
+    Ok(specifiers)
+}
+
+fn parse(
+    name: &str,
+    source: &str,
+    media_type: MediaType,
+) -> Result<deno_ast::ParsedSource, String> {
+    let specifier = ModuleSpecifier::parse("file:///")
+        .and_then(|root| root.join(name))
+        .map_err(|err| format!("{}: {}", name, err))?;
+    deno_ast::parse_module(ParseParams {
+        specifier,
+        text_info: SourceTextInfo::from_string(source.to_string()),
+        media_type,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })
+    .map_err(|err| format!("{}: {}", name, err))
+}
+
+fn emit(name: &str, source: &str) -> Result<String, String> {
+    let media_type = if name.ends_with(".mts") {
+        MediaType::Mts
+    } else {
+        MediaType::TypeScript
+    };
+    let parsed = parse(name, source, media_type)?;
+
+    let transpiled = parsed
+        .transpile(&EmitOptions {
+            inline_source_map: true,
+            inline_sources: true,
+            ..Default::default()
+        })
+        .map_err(|err| format!("Could not transpile {}: {}", name, err))?;
+    Ok(transpiled.text)
+}
+
+/// Where the output for `name` and `source` is cached.
+fn cache_file(name: &str, source: &str) -> Option<PathBuf> {
+    let mut hasher = Sha256::new();
+    for part in [CACHE_VERSION, name, source] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    let key = format!("{:x}", hasher.finalize());
+    cache_dir().map(|dir| dir.join(format!("{}.js", key)))
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("chouten").join("typescript"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_types_and_caches_the_output() {
+        let name = format!("cache-test-{}.ts", std::process::id());
+        let source = "interface Show { title: string }\nconst answer: number = 42;\n";
+        let output = transpile(&name, source).unwrap();
+        assert!(output.contains("const answer = 42;"), "{}", output);
+        assert!(!output.contains("interface"), "{}", output);
+        assert!(output.contains("//# sourceMappingURL=data:application/json;base64,"));
+
+        // Failing to write the cache is not an error, so there may be none.
+        let path = match cache_file(&name, source) {
+            Some(path) if path.is_file() => path,
+            _ => return,
+        };
+        assert_eq!(fs::read_to_string(&path).unwrap(), output);
+        fs::write(&path, "cached").unwrap();
+        let cached = transpile(&name, source);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cached.unwrap(), "cached");
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_the_file_name() {
+        let err = transpile("broken.ts", "const answer: = 42;").unwrap_err();
+        assert!(err.starts_with("broken.ts: "), "{}", err);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::cassette::Cassette;
+use crate::cli::Method;
+use crate::probe;
+use crate::runtime::{self, Script};
+use crate::schema;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How to walk a module.
+pub struct Options {
+    /// Start from `search(query)` instead of `discover()`.
+    pub query: Option<String>,
+    /// How many entries to follow at every level.
+    pub limit: usize,
+    /// Check that the streams and subtitles of every `sources()` result
+    /// can be loaded.
+    pub probe: bool,
+    pub timeout: Duration,
+    pub cassette: Option<Arc<Cassette>>,
+}
+
+/// Calls, failures and time spent per entry point.
+#[derive(Default)]
+struct Level {
+    calls: usize,
+    failed: usize,
+    elapsed: Duration,
+}
+
+struct Walker<'a> {
+    script: &'a Script,
+    options: &'a Options,
+    levels: [Level; 6],
+}
+
+/// Calls entry points the way the app does: `discover()` or `search(query)`,
+/// then `info()` and `media()` for the first titles, `servers()` for their
+/// first episodes and `sources()` for the first servers. Prints the tree of
+/// calls as it goes, followed by a summary per entry point, and returns how
+/// many calls failed.
+pub fn walk(script: &Script, options: &Options) -> usize {
+    let mut walker = Walker {
+        script,
+        options,
+        levels: Default::default(),
+    };
+
+    match &options.query {
+        Some(query) => walker.step(Method::Search, query, ""),
+        None => walker.step(Method::Discover, "", ""),
+    }
+
+    println!();
+    println!(
+        "{:<10} {:>6} {:>7} {:>10}",
+        "entry", "calls", "failed", "time"
+    );
+    let mut failed = 0;
+    for (method, level) in Method::ALL.iter().zip(&walker.levels) {
+        if level.calls == 0 {
+            continue;
+        }
+        failed += level.failed;
+        println!(
+            "{:<10} {:>6} {:>7} {:>9.2}s",
+            method.name(),
+            level.calls,
+            level.failed,
+            level.elapsed.as_secs_f64()
+        );
+    }
+    failed
+}
+
+impl Walker<'_> {
+    /// Calls `method` with `arg`, prints the outcome on a line starting with
+    /// `prefix` and walks on into the entries it returned.
+    fn step(&mut self, method: Method, arg: &str, prefix: &str) {
+        let args = match method {
+            Method::Discover => Vec::new(),
+            _ => vec![Value::String(arg.to_string())],
+        };
+        let call = match method {
+            Method::Discover => "discover()".to_string(),
+            _ => format!("{}({:?})", method.name(), arg),
+        };
+
+        let started = Instant::now();
+        let result = runtime::invoke(
+            self.script,
+            method,
+            &args,
+            self.options.timeout,
+            self.options.cassette.as_ref(),
+        );
+        let elapsed = started.elapsed();
+
+        let level = &mut self.levels[Method::ALL.iter().position(|&m| m == method).unwrap()];
+        level.calls += 1;
+        level.elapsed += elapsed;
+
+        let result = result.map_err(|err| err.to_string()).and_then(|json| {
+            json.as_deref()
+                .map(serde_json::from_str)
+                .transpose()
+                .map(|value| value.unwrap_or(Value::Null))
+                .map_err(|err| format!("Result could not be read back as JSON: {}", err))
+        });
+        let value = match result {
+            Ok(value) => value,
+            Err(err) => {
+                level.failed += 1;
+                println!("{}  FAILED  {:.2}s", call, elapsed.as_secs_f64());
+                for line in err.lines() {
+                    println!("{}  ! {}", prefix, line);
+                }
+                return;
+            }
+        };
+
+        let items = schema::items(method, &value);
+        let summary = match method {
+            Method::Info => String::new(),
+            Method::Sources => format!(
+                "  {} streams, {} subtitles",
+                items.len(),
+                value["subtitles"].as_array().map_or(0, Vec::len)
+            ),
+            _ => format!("  {} entries", items.len()),
+        };
+        println!("{}{}  {:.2}s", call, summary, elapsed.as_secs_f64());
+
+        let violations = schema::validate(method, &value, self.script.module_type);
+        let mut failed = !violations.is_empty();
+        for violation in &violations {
+            println!("{}  ! {}", prefix, violation);
+        }
+
+        if method == Method::Sources && self.options.probe {
+            match probe::probe(&value, self.options.cassette.as_ref()) {
+                Ok(reports) => {
+                    for report in &reports {
+                        failed |= report.failures() > 0;
+                        for line in report.to_string().lines() {
+                            println!("{}  {}", prefix, line);
+                        }
+                    }
+                }
+                Err(err) => {
+                    failed = true;
+                    println!("{}  ! {}", prefix, err);
+                }
+            }
+        }
+        if failed {
+            level.failed += 1;
+        }
+
+        let children: &[Method] = match method {
+            Method::Discover | Method::Search => &[Method::Info, Method::Media],
+            Method::Media => &[Method::Servers],
+            Method::Servers => &[Method::Sources],
+            Method::Info | Method::Sources => return,
+        };
+
+        let items: Vec<_> = items.into_iter().take(self.options.limit).collect();
+        for (index, (path, item)) in items.iter().enumerate() {
+            let last = index + 1 == items.len();
+            let (branch, indent) = if last {
+                ("└─ ", "   ")
+            } else {
+                ("├─ ", "│  ")
+            };
+            println!("{}{}{}", prefix, branch, label(method, item));
+
+            let prefix = format!("{}{}", prefix, indent);
+            let url = match item["url"].as_str() {
+                Some(url) => url,
+                None => {
+                    println!("{}! {}.url: no url to follow", prefix, path);
+                    continue;
+                }
+            };
+            for (index, &child) in children.iter().enumerate() {
+                let last = index + 1 == children.len();
+                let (branch, indent) = if last {
+                    ("└─ ", "   ")
+                } else {
+                    ("├─ ", "│  ")
+                };
+                print!("{}{}", prefix, branch);
+                self.step(child, url, &format!("{}{}", prefix, indent));
+            }
+        }
+    }
+}
+
+/// A short name for an entry, as the app would show it.
+fn label(method: Method, item: &Value) -> String {
+    let label = match method {
+        Method::Discover | Method::Search => item["titles"]["primary"].as_str().map(str::to_string),
+        Method::Media => match item["title"].as_str() {
+            Some(title) if !title.is_empty() => Some(title.to_string()),
+            _ => item["number"].as_f64().map(|number| format!("#{}", number)),
+        },
+        Method::Servers => item["name"].as_str().map(str::to_string),
+        Method::Info | Method::Sources => None,
+    };
+    label.unwrap_or_else(|| "(untitled)".to_string())
+}
@@ -0,0 +1,356 @@
+use crate::runtime::set_function;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use url::{form_urlencoded, quirks, Url};
+
+/// The base64 of `atob`, which accepts input without padding and ignores
+/// leftover bits, as the HTML spec's forgiving-base64 decode does.
+const FORGIVING: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::RequireNone)
+        .with_decode_allow_trailing_bits(true),
+);
+
+/// The host functions `js/web.js` builds `URL`, `URLSearchParams`,
+/// `TextEncoder`, `TextDecoder`, `atob` and `btoa` on, as the object
+/// `__web`. Invalid input comes back as `null`, so the script can throw the
+/// error the spec asks for.
+pub fn host_object<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Object> {
+    let object = v8::Object::new(scope);
+    set_function(scope, object, "parseUrl", parse_url);
+    set_function(scope, object, "setUrl", set_url);
+    set_function(scope, object, "parseQuery", parse_query);
+    set_function(scope, object, "serializeQuery", serialize_query);
+    set_function(scope, object, "encodeUtf8", encode_utf8);
+    set_function(scope, object, "decodeUtf8", decode_utf8);
+    set_function(scope, object, "atob", atob);
+    set_function(scope, object, "btoa", btoa);
+    object
+}
+
+/// `parseUrl(input, base)`: the components of a URL, resolved against
+/// `base` unless it is `undefined`.
+fn parse_url(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let input = args.get(0).to_rust_string_lossy(scope);
+    let base = args.get(1);
+    let parsed = if base.is_undefined() {
+        Url::parse(&input)
+    } else {
+        let base = base.to_rust_string_lossy(scope);
+        Url::parse(&base).and_then(|base| base.join(&input))
+    };
+
+    match parsed {
+        Ok(url) => rv.set(components(scope, &url).into()),
+        Err(_) => rv.set_null(),
+    }
+}
+
+/// `setUrl(href, name, value)`: the components of a URL after assigning one
+/// of its properties. Values a property rejects leave the URL as it was,
+/// except for `href`, where they return `null`.
+fn set_url(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let href = args.get(0).to_rust_string_lossy(scope);
+    let name = args.get(1).to_rust_string_lossy(scope);
+    let value = args.get(2).to_rust_string_lossy(scope);
+    let mut url = match Url::parse(&href) {
+        Ok(url) => url,
+        Err(_) => {
+            rv.set_null();
+            return;
+        }
+    };
+
+    let accepted = match name.as_str() {
+        "href" => quirks::set_href(&mut url, &value).is_ok(),
+        "protocol" => quirks::set_protocol(&mut url, &value).is_ok(),
+        "username" => quirks::set_username(&mut url, &value).is_ok(),
+        "password" => quirks::set_password(&mut url, &value).is_ok(),
+        "host" => quirks::set_host(&mut url, &value).is_ok(),
+        "hostname" => quirks::set_hostname(&mut url, &value).is_ok(),
+        "port" => quirks::set_port(&mut url, &value).is_ok(),
+        "pathname" => {
+            quirks::set_pathname(&mut url, &value);
+            true
+        }
+        "search" => {
+            quirks::set_search(&mut url, &value);
+            true
+        }
+        "hash" => {
+            quirks::set_hash(&mut url, &value);
+            true
+        }
+        _ => true,
+    };
+    if !accepted && name == "href" {
+        rv.set_null();
+        return;
+    }
+    rv.set(components(scope, &url).into());
+}
+
+fn components<'s>(scope: &mut v8::HandleScope<'s>, url: &Url) -> v8::Local<'s, v8::Object> {
+    let origin = quirks::origin(url);
+    let fields = [
+        ("href", quirks::href(url)),
+        ("origin", origin.as_str()),
+        ("protocol", quirks::protocol(url)),
+        ("username", quirks::username(url)),
+        ("password", quirks::password(url)),
+        ("host", quirks::host(url)),
+        ("hostname", quirks::hostname(url)),
+        ("port", quirks::port(url)),
+        ("pathname", quirks::pathname(url)),
+        ("search", quirks::search(url)),
+        ("hash", quirks::hash(url)),
+    ];
+
+    let object = v8::Object::new(scope);
+    for (name, value) in fields {
+        let name = v8::String::new(scope, name).unwrap();
+        let value = v8::String::new(scope, value).unwrap();
+        object.set(scope, name.into(), value.into());
+    }
+    object
+}
+
+/// `parseQuery(query)`: the name and value pairs of an
+/// `application/x-www-form-urlencoded` string, as an array of arrays.
+fn parse_query(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let query = args.get(0).to_rust_string_lossy(scope);
+    let pairs: Vec<v8::Local<v8::Value>> = form_urlencoded::parse(query.as_bytes())
+        .map(|(name, value)| {
+            let name = v8::String::new(scope, &name).unwrap().into();
+            let value = v8::String::new(scope, &value).unwrap().into();
+            v8::Array::new_with_elements(scope, &[name, value]).into()
+        })
+        .collect();
+    rv.set(v8::Array::new_with_elements(scope, &pairs).into());
+}
+
+/// `serializeQuery(pairs)`: the `application/x-www-form-urlencoded` string
+/// of an array of name and value pairs.
+fn serialize_query(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    if let Ok(pairs) = v8::Local::<v8::Array>::try_from(args.get(0)) {
+        for index in 0..pairs.length() {
+            let pair = pairs.get_index(scope, index).unwrap();
+            let pair = match v8::Local::<v8::Object>::try_from(pair) {
+                Ok(pair) => pair,
+                Err(_) => continue,
+            };
+            let name = pair.get_index(scope, 0).unwrap();
+            let value = pair.get_index(scope, 1).unwrap();
+            query.append_pair(
+                &name.to_rust_string_lossy(scope),
+                &value.to_rust_string_lossy(scope),
+            );
+        }
+    }
+    let query = v8::String::new(scope, &query.finish()).unwrap();
+    rv.set(query.into());
+}
+
+/// `encodeUtf8(string)`: the UTF-8 bytes of a string as a `Uint8Array`.
+/// Lone surrogates become U+FFFD.
+fn encode_utf8(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let bytes = args.get(0).to_rust_string_lossy(scope).into_bytes();
+    let length = bytes.len();
+    let store = v8::ArrayBuffer::new_backing_store_from_vec(bytes).make_shared();
+    let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+    let view = v8::Uint8Array::new(scope, buffer, 0, length).unwrap();
+    rv.set(view.into());
+}
+
+/// `decodeUtf8(bytes, fatal, stream)`: decodes a `Uint8Array` and returns
+/// the text along with how many bytes at the end were left for the next
+/// call, which only happens when streaming. Returns `null` for invalid
+/// UTF-8 when `fatal` is set.
+fn decode_utf8(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let bytes = match v8::Local::<v8::ArrayBufferView>::try_from(args.get(0)) {
+        Ok(view) => {
+            let mut bytes = vec![0; view.byte_length()];
+            view.copy_contents(&mut bytes);
+            bytes
+        }
+        Err(_) => Vec::new(),
+    };
+    let fatal = args.get(1).boolean_value(scope);
+    let stream = args.get(2).boolean_value(scope);
+
+    match decode(&bytes, fatal, stream) {
+        Some((text, pending)) => {
+            let text = v8::String::new(scope, &text).unwrap().into();
+            let pending = v8::Integer::new_from_unsigned(scope, pending as u32).into();
+            rv.set(v8::Array::new_with_elements(scope, &[text, pending]).into());
+        }
+        None => rv.set_null(),
+    }
+}
+
+/// Decodes UTF-8 the way the Encoding spec does: every maximal invalid
+/// sequence becomes one U+FFFD, or fails the decode when `fatal` is set. An
+/// incomplete sequence at the end is kept for later when `stream` is set.
+fn decode(bytes: &[u8], fatal: bool, stream: bool) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return Some((text, 0));
+            }
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap());
+                match err.error_len() {
+                    None if stream => return Some((text, invalid.len())),
+                    _ if fatal => return None,
+                    None => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        return Some((text, 0));
+                    }
+                    Some(length) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &invalid[length..];
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `atob(data)`: decodes base64 into a string with one character per byte.
+fn atob(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut rv: v8::ReturnValue) {
+    let data = args.get(0).to_rust_string_lossy(scope);
+    match forgiving_decode(&data) {
+        Some(bytes) => {
+            let text: String = bytes.into_iter().map(char::from).collect();
+            let text = v8::String::new(scope, &text).unwrap();
+            rv.set(text.into());
+        }
+        None => rv.set_null(),
+    }
+}
+
+/// The forgiving-base64 decode of the HTML spec, which `atob` uses.
+fn forgiving_decode(data: &str) -> Option<Vec<u8>> {
+    let mut data: String = data
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\x0c' | '\r' | ' '))
+        .collect();
+    if data.len().is_multiple_of(4) {
+        for _ in 0..2 {
+            if data.ends_with('=') {
+                data.pop();
+            }
+        }
+    }
+    // A length of one more than a multiple of four leaves a lone sextet.
+    if (data.len() + 3).is_multiple_of(4) {
+        return None;
+    }
+    FORGIVING.decode(data).ok()
+}
+
+/// `btoa(data)`: encodes a string with one byte per character as base64, or
+/// returns `null` when a character is outside Latin-1.
+fn btoa(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut rv: v8::ReturnValue) {
+    let data = args.get(0).to_rust_string_lossy(scope);
+    let bytes: Option<Vec<u8>> = data.chars().map(|c| u8::try_from(c).ok()).collect();
+    match bytes {
+        Some(bytes) => {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+            let encoded = v8::String::new(scope, &encoded).unwrap();
+            rv.set(encoded.into());
+        }
+        None => rv.set_null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_valid_utf8() {
+        let decoded = decode("héllo ✓ 𝄞".as_bytes(), true, false);
+        assert_eq!(decoded, Some(("héllo ✓ 𝄞".to_string(), 0)));
+    }
+
+    #[test]
+    fn replaces_each_maximal_invalid_sequence() {
+        // A truncated three byte sequence, a lone continuation byte and a
+        // byte that never appears in UTF-8.
+        let bytes = b"a\xe2\x9cb\x80c\xff";
+        let decoded = decode(bytes, false, false);
+        assert_eq!(
+            decoded,
+            Some(("a\u{fffd}b\u{fffd}c\u{fffd}".to_string(), 0))
+        );
+        assert_eq!(decode(bytes, true, false), None);
+    }
+
+    #[test]
+    fn keeps_an_incomplete_sequence_while_streaming() {
+        let check = "✓".as_bytes();
+        assert_eq!(decode(&check[..2], false, true), Some((String::new(), 2)));
+        assert_eq!(decode(&check[..2], true, true), Some((String::new(), 2)));
+        assert_eq!(
+            decode(&check[..2], false, false),
+            Some(("\u{fffd}".to_string(), 0))
+        );
+        assert_eq!(decode(&check[..2], true, false), None);
+
+        // Only an incomplete sequence at the very end is held back.
+        assert_eq!(
+            decode(b"\xe2\x9cx\xe2", false, true),
+            Some(("\u{fffd}x".to_string(), 1))
+        );
+    }
+
+    #[test]
+    fn forgiving_base64_accepts_missing_padding_and_whitespace() {
+        assert_eq!(forgiving_decode("aGk="), Some(b"hi".to_vec()));
+        assert_eq!(forgiving_decode("aGk"), Some(b"hi".to_vec()));
+        assert_eq!(forgiving_decode(" aG\tk\n= "), Some(b"hi".to_vec()));
+        assert_eq!(forgiving_decode("YQ=="), Some(b"a".to_vec()));
+        assert_eq!(forgiving_decode(""), Some(Vec::new()));
+        // Bits left over in the last character are ignored.
+        assert_eq!(forgiving_decode("YR"), Some(b"a".to_vec()));
+    }
+
+    #[test]
+    fn forgiving_base64_rejects_malformed_input() {
+        for data in ["a", "aGk==", "aG=k", "YQ=", "a-b_", "aGk*"] {
+            assert_eq!(forgiving_decode(data), None, "{:?} should not decode", data);
+        }
+    }
+}